@@ -1,22 +1,32 @@
-use std::{io::Cursor, sync::Arc, time::Duration};
+use std::{
+  collections::{HashMap, HashSet},
+  io::Cursor,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::{Duration, SystemTime},
+};
 
 use super::*;
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use futures_core::Stream;
 use futures_util::{SinkExt, StreamExt};
 
 use log::warn;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use reqwest::Url;
 use tokio::{
   sync::{
     mpsc::{self, Receiver},
-    RwLock,
+    oneshot, RwLock,
   },
   task::JoinHandle,
 };
 use tokio_tungstenite as tokio_ws2;
 use tokio_ws2::{
-  connect_async,
+  client_async_tls_with_config, connect_async_with_config,
   tungstenite::{self as ws2, protocol::WebSocketConfig},
 };
 
@@ -25,6 +35,13 @@ use crate::{
   data::live::{cmds::*, *},
 };
 
+mod builder;
+mod proxy;
+mod reconnect;
+
+pub use builder::*;
+pub use reconnect::*;
+
 #[allow(dead_code)]
 impl Live<'_> {
   get_query_json_resp_fn!(
@@ -39,26 +56,96 @@ impl Live<'_> {
 pub struct MessageConnection<CMD: Cmd = MaybeCommand> {
   heartbeat_job: Option<JoinHandle<()>>,
   main_job: Option<JoinHandle<()>>,
-  rx: Receiver<CMD>,
+  rx: Receiver<ConnectionEvent<CMD>>,
   close: bool,
+  stats: ConnectionStats,
 }
 
-#[allow(dead_code)]
-impl<CMD: Cmd> MessageConnection<CMD> {
-  pub async fn connect_with_client(
+/// Hosts failed to connect, with the time of the failure
+static FAILED_HOSTS: Lazy<Mutex<HashMap<String, std::time::Instant>>> = Lazy::new(Default::default);
+
+/// A failed host is tried after the others for this long
+const HOST_FAILURE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Timeout of connecting a single host, so an unresponsive host doesn't block the failover
+const HOST_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Urls of hosts failed within [`HOST_FAILURE_TTL`]
+fn failed_hosts() -> HashSet<String> {
+  let mut failed = FAILED_HOSTS.lock();
+  failed.retain(|_, time| time.elapsed() < HOST_FAILURE_TTL);
+  failed.keys().cloned().collect()
+}
+
+/// Items of a [`MessageConnection`]
+#[derive(Debug)]
+pub enum ConnectionEvent<CMD = MaybeCommand> {
+  /// The certificate is accepted, commands of the room are coming
+  Connected,
+  /// The certificate is rejected with the code, the server closes the connection then
+  AuthFailed(i32),
+  /// Popularity in a heartbeat response, also kept as [`MessageConnection::last_heartbeat`]
+  Popularity(u32),
+  /// A packet failed to decode and is skipped
+  DecodeError(String),
+  /// The connection is closed with the reason, no more events follow
+  Closed(String),
+  Command(CMD),
+}
+
+/// The last `HeartbeatResp` received
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatInfo {
+  pub popular: u32,
+  pub received_at: SystemTime,
+}
+
+/// Updated by the jobs of a connection, shared by connections of a [`ReconnectingConnection`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionStats {
+  last_heartbeat: Arc<Mutex<Option<HeartbeatInfo>>>,
+  bytes_received: Arc<AtomicU64>,
+}
+
+/// Identity sent in the certificate packet, it doesn't change when reconnecting
+#[derive(Debug, Clone)]
+pub struct RoomAuth {
+  pub mid: u64,
+  /// Real room id, short ids are resolved
+  pub room_id: u64,
+  pub buvid: String,
+}
+
+impl RoomAuth {
+  /// Identity of the logged in account, fails if the client has no login cookies
+  pub async fn fetch(client: &Client, room_id: u64) -> anyhow::Result<RoomAuth> {
+    Self::fetch_as(client, room_id, false).await
+  }
+
+  /// Identity of a guest with uid 0, no login is needed.
+  /// Bilibili masks usernames in the commands received by guests.
+  pub async fn fetch_guest(client: &Client, room_id: u64) -> anyhow::Result<RoomAuth> {
+    Self::fetch_as(client, room_id, true).await
+  }
+
+  pub(crate) async fn fetch_as(
     client: &Client,
     room_id: u64,
-  ) -> anyhow::Result<Arc<RwLock<Self>>> {
+    guest: bool,
+  ) -> anyhow::Result<RoomAuth> {
     let mid = {
       let client = Client::clone(client);
       async move {
+        if guest {
+          return Ok(0);
+        }
         client
           .info()
           .get_nav_info()
           .await?
           .data
           .mid
-          .context("NavInfo $.data.mid is None")
+          .context("NavInfo $.data.mid is None, log in or connect as a guest")
       }
     };
     let real_room_id = {
@@ -91,28 +178,102 @@ impl<CMD: Cmd> MessageConnection<CMD> {
     };
 
     let (mid, real_room_id, buvid) = tokio::join!(mid, real_room_id, buvid);
-    let mid = mid?;
-    let real_room_id = real_room_id?;
-    let buvid = buvid?;
+    Ok(RoomAuth {
+      mid: mid?,
+      room_id: real_room_id?,
+      buvid: buvid?,
+    })
+  }
+}
+
+#[allow(dead_code)]
+impl<CMD: Cmd> MessageConnection<CMD> {
+  pub async fn connect_with_client(
+    client: &Client,
+    room_id: u64,
+  ) -> anyhow::Result<Arc<RwLock<Self>>> {
+    Self::builder(client).room(room_id).connect().await
+  }
 
+  /// Connects with options, e.g. `MessageConnection::builder(&client).room(1).protocol(Zlib)`
+  pub fn builder(client: &Client) -> ConnectionBuilder<CMD> {
+    ConnectionBuilder::new(client)
+  }
+
+  /// Connects with a fresh token, trying every host of the host list starting at `host_offset`,
+  /// then their `ws://` variants. Hosts failed recently are tried last.
+  /// Returns the connection and the url connected.
+  pub(crate) async fn connect_with_auth(
+    client: &Client,
+    auth: &RoomAuth,
+    host_offset: usize,
+    protocol: Protocol,
+    config: &NetworkConfig,
+    stats: ConnectionStats,
+  ) -> anyhow::Result<(Arc<RwLock<Self>>, String)> {
     let danmaku = client
       .live()
-      .danmaku_info(&room_id.into())
+      .danmaku_info(&auth.room_id.into())
       .await
       .context("Failed to get DanmakuResp")?;
     let danmaku_data = danmaku.data.context("DanmakuResp $.data is None")?;
-    let host_data = danmaku_data
-      .host_list
-      .first()
-      .context("DanmakuResp $.data.host_list is empty")?;
-    let key = danmaku_data.token.clone();
-    let url = host_data
-      .to_url()
-      .with_context(|| format!("Failed to convert WssHost to Url: {:?}", host_data))?;
-
-    Self::connect(url, mid, real_room_id, key, buvid, Protocol::Brotli).await
+    let hosts = &danmaku_data.host_list;
+    if hosts.is_empty() {
+      bail!("DanmakuResp $.data.host_list is empty");
+    }
+
+    let ordered = || {
+      hosts
+        .iter()
+        .cycle()
+        .skip(host_offset % hosts.len())
+        .take(hosts.len())
+    };
+    let mut urls: Vec<Url> = ordered()
+      .map(WssHost::to_url)
+      .chain(ordered().map(WssHost::to_ws_url))
+      .filter_map(|url| url.inspect_err(|err| warn!("Invalid host: {err}")).ok())
+      .collect();
+    // Stable, so the order is kept among hosts failed recently and the others
+    let failed = failed_hosts();
+    urls.sort_by_key(|url| failed.contains(url.as_str()));
+
+    let mut errors = Vec::with_capacity(urls.len());
+    for url in urls {
+      let cert = Certificate {
+        mid: Some(auth.mid),
+        buvid: Some(auth.buvid.clone()),
+        platform: Some("web".to_string()),
+        room_id: auth.room_id,
+        key: danmaku_data.token.clone(),
+        protocol,
+      };
+      let connect = Self::connect_with_stats(url.clone(), cert, config, stats.clone());
+      let result = tokio::time::timeout(HOST_CONNECT_TIMEOUT, connect)
+        .await
+        .unwrap_or_else(|_elapsed| Err(anyhow!("Timed out")));
+      match result {
+        Ok(con) => {
+          FAILED_HOSTS.lock().remove(url.as_str());
+          return Ok((con, url.to_string()));
+        },
+        Err(err) => {
+          warn!("Failed to connect {url}, trying the next host: {err:#}");
+          FAILED_HOSTS
+            .lock()
+            .insert(url.to_string(), std::time::Instant::now());
+          errors.push(format!("{url}: {err:#}"));
+        },
+      }
+    }
+    bail!(
+      "Failed to connect every host of room {}: {}",
+      auth.room_id,
+      errors.join("; ")
+    )
   }
 
+  /// Connects to the host with the default [`NetworkConfig`], see [`Self::builder`] for options
   pub async fn connect(
     url: Url,
     mid: u64,
@@ -121,34 +282,66 @@ impl<CMD: Cmd> MessageConnection<CMD> {
     buvid: String,
     protocol: Protocol,
   ) -> anyhow::Result<Arc<RwLock<Self>>> {
-    let config = NetworkConfig::default();
+    let cert = Certificate {
+      mid: Some(mid),
+      buvid: Some(buvid),
+      platform: Some("web".to_string()),
+      room_id,
+      key,
+      protocol,
+    };
+    Self::connect_with_stats(
+      url,
+      cert,
+      &NetworkConfig::default(),
+      ConnectionStats::default(),
+    )
+    .await
+  }
 
-    let (ws, _) = connect_async(url.to_string())
-      .await
-      .with_context(|| format!("Failed to connect WebSocket: {:?}", url))?;
+  async fn connect_with_stats(
+    url: Url,
+    cert: Certificate,
+    config: &NetworkConfig,
+    stats: ConnectionStats,
+  ) -> anyhow::Result<Arc<RwLock<Self>>> {
+    let ws_config = Some(config.websocket_config);
+    let ws = match config.proxy {
+      Some(ref proxy) => {
+        let host = url.host_str().context("WebSocket url has no host")?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let stream = proxy::connect(proxy, host, port)
+          .await
+          .with_context(|| format!("Failed to connect {host}:{port} through proxy"))?;
+        client_async_tls_with_config(url.as_str(), stream, ws_config, None).await
+      },
+      None => connect_async_with_config(url.as_str(), ws_config, false).await,
+    };
+    let (ws, _) = ws.with_context(|| format!("Failed to connect WebSocket: {:?}", url))?;
+    let heartbeat_interval = match config.heartbeat_interval {
+      Duration::ZERO => Duration::from_secs(1),
+      interval => interval,
+    };
 
-    let (tx, rx) = mpsc::channel::<CMD>(config.channel_buffer);
+    let (tx, rx) = mpsc::channel::<ConnectionEvent<CMD>>(config.channel_buffer.max(1));
 
+    let ConnectionStats {
+      last_heartbeat,
+      bytes_received,
+    } = stats.clone();
     let con = MessageConnection {
       heartbeat_job: None,
       main_job: None,
       rx,
       close: false,
+      stats,
     };
     let con = Arc::new(RwLock::new(con));
 
     let (mut wss_tx, mut wss_rx) = ws.split();
 
     {
-      let msg = Certificate {
-        mid: Some(mid),
-        buvid: Some(buvid),
-        platform: Some("web".to_string()),
-        room_id,
-        key,
-        protocol,
-      }
-      .with_head(1);
+      let msg = cert.with_head(1);
       log::debug!("Send Certificate Packet: {:?}", msg);
       let binary = msg
         .into_binary_frame()
@@ -159,10 +352,22 @@ impl<CMD: Cmd> MessageConnection<CMD> {
         .context("Failed to send cert packet")?;
     }
 
+    // Only the main job reports `Closed`, so a failed heartbeat is passed to it
+    let (heartbeat_failed_tx, mut heartbeat_failed) = oneshot::channel::<String>();
     let heartbeat_job = tokio::spawn({
-      let con = Arc::clone(&con);
+      // Weak, so dropping the connection closes it, see `Drop for MessageConnection`
+      let con = Arc::downgrade(&con);
       async move {
-        while !con.read().await.should_close() {
+        loop {
+          let Some(con) = con.upgrade() else {
+            return;
+          };
+          if con.read().await.should_close() {
+            con.write().await.close();
+            return;
+          }
+          drop(con);
+
           let msg = Message::heartbeat(1);
           log::debug!("Send Heartbeat Packet: {:?}", msg);
           let binary = msg
@@ -172,69 +377,104 @@ impl<CMD: Cmd> MessageConnection<CMD> {
 
           if let Err(err) = wss_tx.send(binary).await {
             log::error!("heartbeat err: {:?}", err);
-            break;
+            let _ = heartbeat_failed_tx.send(format!("Failed to send heartbeat: {err}"));
+            return;
           };
 
-          tokio::time::sleep(config.heartbeat_interval).await;
+          tokio::time::sleep(heartbeat_interval).await;
         }
-        con.write().await.close();
       }
     });
     con.write().await.heartbeat_job = Some(heartbeat_job);
 
     let main_job = tokio::spawn({
       async move {
-        while let Some(msg) = wss_rx.next().await {
-          use ws2::error::ProtocolError::*;
-          use ws2::Error::*;
-          use MessagePayload::{self as Payload, *};
+        use ws2::error::ProtocolError::*;
+        use ws2::Error::*;
+        use MessagePayload::{self as Payload, *};
 
+        let mut close_frame: Option<String> = None;
+        let mut heartbeat_stopped = false;
+        let reason = 'main: loop {
+          let msg = tokio::select! {
+            msg = wss_rx.next() => msg,
+            failed = &mut heartbeat_failed, if !heartbeat_stopped => match failed {
+              Ok(reason) => break 'main reason,
+              // Stopped because the connection is closed
+              Err(_) => {
+                heartbeat_stopped = true;
+                continue;
+              },
+            },
+          };
+          let Some(msg) = msg else {
+            break close_frame.unwrap_or_else(|| "WebSocket stream ended".to_string());
+          };
           let msg = match msg {
             Ok(ok) => ok,
             Err(err) => match err {
               ConnectionClosed | Protocol(ResetWithoutClosingHandshake) => {
                 log::debug!("Remote closed: {}", &url);
-                drop(wss_rx);
-                break;
+                break close_frame.unwrap_or_else(|| "Remote closed".to_string());
               },
               err => {
                 log::error!("Unexpected ws error: {err:?}");
-                drop(wss_rx);
-                break;
+                break format!("WebSocket error: {err}");
               },
             },
           };
 
-          let ws2::Message::Binary(binary) = msg else {
-            continue;
-          };
-          let mut cursor = Cursor::new(binary);
-          let payload = match Payload::<CMD>::from_reader(&mut cursor) {
-            Ok(payload) => payload,
-            Err(err) => {
-              warn!("Failed to read pkt {err:?}");
+          bytes_received.fetch_add(msg.len() as u64, Ordering::Relaxed);
+          let binary = match msg {
+            ws2::Message::Binary(binary) => binary,
+            ws2::Message::Close(frame) => {
+              close_frame = Some(match frame {
+                Some(frame) => format!("Remote closed: {} {}", frame.code, frame.reason),
+                None => "Remote closed".to_string(),
+              });
               continue;
             },
+            _ => continue,
           };
-          match payload {
-            ref payload @ HeartbeatResp { .. } | ref payload @ CertificateResp(_) => {
-              log::debug!("{payload:?}");
+          let mut cursor = Cursor::new(binary);
+          let event = match Payload::<CMD>::from_reader(&mut cursor) {
+            Ok(HeartbeatResp { popular }) => {
+              log::debug!("HeartbeatResp {{ popular: {popular} }}");
+              *last_heartbeat.lock() = Some(HeartbeatInfo {
+                popular,
+                received_at: SystemTime::now(),
+              });
+              ConnectionEvent::Popularity(popular)
+            },
+            Ok(CertificateResp(resp)) => {
+              log::debug!("{resp:?}");
+              match resp.is_success() {
+                true => ConnectionEvent::Connected,
+                false => ConnectionEvent::AuthFailed(resp.code),
+              }
             },
-            Command(cmds) => {
+            Ok(Command(cmds)) => {
               for cmd in cmds {
-                if let Err(err) = tx
-                  .send(cmd)
-                  .await
-                  .context("Failed to send Command to channel")
-                {
-                  log::error!("{:?}", err);
-                  break;
+                if tx.send(ConnectionEvent::Command(cmd)).await.is_err() {
+                  break 'main "Receiver dropped".to_string();
                 }
               }
+              continue;
+            },
+            Ok(payload) => {
+              warn!("Unexpected packet {payload}");
+              ConnectionEvent::DecodeError(format!("Unexpected packet {payload}"))
+            },
+            Err(err) => {
+              warn!("Failed to read pkt {err:?}");
+              ConnectionEvent::DecodeError(format!("{err:#}"))
             },
-            _ => unreachable!(),
           };
-        }
+          if tx.send(event).await.is_err() {
+            break "Receiver dropped".to_string();
+          }
+        };
+        let _ = tx.send(ConnectionEvent::Closed(reason)).await;
       }
     });
     con.write().await.main_job = Some(main_job);
@@ -242,6 +482,21 @@ impl<CMD: Cmd> MessageConnection<CMD> {
     Ok(con)
   }
 
+  /// Popularity and time of the last heartbeat response, `None` if not received yet
+  pub fn last_heartbeat(&self) -> Option<HeartbeatInfo> {
+    *self.stats.last_heartbeat.lock()
+  }
+
+  /// Total size of WebSocket messages received, before decompression
+  pub fn bytes_received(&self) -> u64 {
+    self.stats.bytes_received.load(Ordering::Relaxed)
+  }
+
+  /// Number of events received but not consumed yet
+  pub fn backlog(&self) -> usize {
+    self.rx.len()
+  }
+
   fn should_close(&self) -> bool {
     if let Some(ref job) = self.main_job {
       if job.is_finished() {
@@ -278,7 +533,7 @@ impl<CMD: Cmd> MessageConnection<CMD> {
 }
 
 impl<CMD: Cmd> Stream for MessageConnection<CMD> {
-  type Item = CMD;
+  type Item = ConnectionEvent<CMD>;
 
   #[inline]
   fn poll_next(
@@ -295,13 +550,15 @@ impl<CMD: Cmd> Drop for MessageConnection<CMD> {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NetworkConfig {
-  /// The interval of sending heartbeat packet, the default is 30 seconds.
+  /// The interval of sending heartbeat packet, the default is 30 seconds. Zero is taken as 1 second.
   pub heartbeat_interval: Duration,
-  /// The size of the mpsc channel. Backpressure is controlled by this option.
+  /// The size of the mpsc channel, at least 1. Backpressure is controlled by this option.
   pub channel_buffer: usize,
   pub websocket_config: WebSocketConfig,
+  /// Proxy of the WebSocket, `http`, `socks5` and `socks5h` are supported
+  pub proxy: Option<Url>,
 }
 
 impl Default for NetworkConfig {
@@ -310,6 +567,59 @@ impl Default for NetworkConfig {
       heartbeat_interval: Duration::from_secs(30),
       channel_buffer: 64,
       websocket_config: Default::default(),
+      proxy: None,
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use tokio::net::TcpListener;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn dropping_stops_heartbeat() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("ws://{}/sub", listener.local_addr().unwrap())).unwrap();
+    let server = tokio::spawn(async move {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut ws = tokio_ws2::accept_async(stream).await.unwrap();
+      // The certificate and a few heartbeats
+      for _ in 0..3 {
+        ws.next().await.unwrap().unwrap();
+      }
+      ws
+    });
+
+    let cert = Certificate {
+      mid: Some(0),
+      buvid: None,
+      platform: None,
+      room_id: 1,
+      key: String::new(),
+      protocol: Protocol::Brotli,
+    };
+    let config = NetworkConfig {
+      heartbeat_interval: Duration::from_millis(10),
+      ..Default::default()
+    };
+    let con = MessageConnection::<MaybeCommand>::connect_with_stats(
+      url,
+      cert,
+      &config,
+      ConnectionStats::default(),
+    )
+    .await
+    .unwrap();
+    let mut ws = server.await.unwrap();
+    drop(con);
+
+    // Heartbeats sent before the drop may still arrive, then the socket is closed
+    let closed = tokio::time::timeout(Duration::from_secs(2), async {
+      while let Some(Ok(_)) = ws.next().await {}
+    })
+    .await;
+    assert!(closed.is_ok(), "heartbeat is still running");
+  }
+}
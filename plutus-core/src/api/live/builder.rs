@@ -0,0 +1,118 @@
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use anyhow::{ensure, Context};
+use reqwest::Url;
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+use super::{
+  Backoff, ConnectionStats, MessageConnection, NetworkConfig, ReconnectingConnection, RoomAuth,
+};
+use crate::{
+  client::Client,
+  data::live::{cmds::Cmd, Protocol},
+};
+
+/// Options of connecting a room, created by [`MessageConnection::builder`]
+pub struct ConnectionBuilder<CMD: Cmd> {
+  client: Client,
+  room_id: Option<u64>,
+  protocol: Protocol,
+  config: NetworkConfig,
+  guest: bool,
+  _cmd: PhantomData<CMD>,
+}
+
+impl<CMD: Cmd> ConnectionBuilder<CMD> {
+  pub(crate) fn new(client: &Client) -> Self {
+    ConnectionBuilder {
+      client: client.clone(),
+      room_id: None,
+      protocol: Protocol::Brotli,
+      config: NetworkConfig::default(),
+      guest: false,
+      _cmd: PhantomData,
+    }
+  }
+
+  /// Room id, a short id also works. Required.
+  pub fn room(mut self, room_id: u64) -> Self {
+    self.room_id = Some(room_id);
+    self
+  }
+
+  /// Compression of commands, the default is Brotli
+  pub fn protocol(mut self, protocol: Protocol) -> Self {
+    self.protocol = protocol;
+    self
+  }
+
+  /// Interval of heartbeats, 0 is taken as 1 second
+  pub fn heartbeat(mut self, interval: Duration) -> Self {
+    self.config.heartbeat_interval = interval;
+    self
+  }
+
+  /// Events buffered for the consumer, 0 is taken as 1
+  pub fn buffer(mut self, size: usize) -> Self {
+    self.config.channel_buffer = size;
+    self
+  }
+
+  pub fn ws_config(mut self, config: WebSocketConfig) -> Self {
+    self.config.websocket_config = config;
+    self
+  }
+
+  /// Proxy of the WebSocket, e.g. `socks5://127.0.0.1:1080`.
+  /// HTTP requests of the handshake use the proxy of the [`Client`].
+  pub fn proxy(mut self, proxy: Url) -> Self {
+    self.config.proxy = Some(proxy);
+    self
+  }
+
+  /// Replaces every network option set before
+  pub fn network_config(mut self, config: NetworkConfig) -> Self {
+    self.config = config;
+    self
+  }
+
+  /// Connects as a guest with uid 0 instead of the logged in account, see [`RoomAuth::fetch_guest`]
+  pub fn guest(mut self, guest: bool) -> Self {
+    self.guest = guest;
+    self
+  }
+
+  pub async fn connect(self) -> anyhow::Result<Arc<RwLock<MessageConnection<CMD>>>> {
+    let room_id = self.room_id.context("Room id is required")?;
+    let auth = RoomAuth::fetch_as(&self.client, room_id, self.guest).await?;
+    let (con, _url) = MessageConnection::connect_with_auth(
+      &self.client,
+      &auth,
+      0,
+      self.protocol,
+      &self.config,
+      ConnectionStats::default(),
+    )
+    .await?;
+    Ok(con)
+  }
+
+  /// Starts a connection reconnecting with `backoff` in the background
+  pub fn reconnecting(self, backoff: Backoff) -> anyhow::Result<ReconnectingConnection<CMD>> {
+    let room_id = self.room_id.context("Room id is required")?;
+    ensure!(
+      backoff.multiplier >= 0.0,
+      "Backoff multiplier must not be negative or NaN: {}",
+      backoff.multiplier
+    );
+    Ok(ReconnectingConnection::start_with(
+      self.client,
+      room_id,
+      backoff,
+      self.protocol,
+      self.config,
+      self.guest,
+    ))
+  }
+}
@@ -0,0 +1,354 @@
+use std::{
+  sync::{atomic::Ordering, Arc},
+  time::Duration,
+};
+
+use futures_core::Stream;
+use futures_util::StreamExt;
+use tokio::{
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    RwLock,
+  },
+  task::JoinHandle,
+  time::Instant,
+};
+
+use super::{
+  ConnectionEvent, ConnectionStats, HeartbeatInfo, MessageConnection, NetworkConfig, RoomAuth,
+};
+use crate::{
+  client::Client,
+  data::live::{
+    cmds::{Cmd, MaybeCommand},
+    Protocol,
+  },
+};
+
+/// The lock of the connection is released this often while waiting for commands,
+/// so its heartbeat job can check whether it is closed.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delay between reconnection attempts, growing exponentially with random jitter
+#[derive(Debug, Clone)]
+pub struct Backoff {
+  pub initial: Duration,
+  pub max: Duration,
+  /// Growth of the delay per attempt, a negative or NaN multiplier is taken as 1.0
+  pub multiplier: f64,
+  /// Fraction of the delay randomly added or subtracted, from 0.0 to 1.0
+  pub jitter: f64,
+}
+
+impl Default for Backoff {
+  fn default() -> Self {
+    Self {
+      initial: Duration::from_secs(1),
+      max: Duration::from_secs(60),
+      multiplier: 2.0,
+      jitter: 0.2,
+    }
+  }
+}
+
+impl Backoff {
+  /// Delay before retrying after `attempt` failures in a row, starting from 0
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let multiplier = if self.multiplier >= 0.0 {
+      self.multiplier
+    } else {
+      1.0
+    };
+    let base = self.initial.as_secs_f64() * multiplier.powi(attempt.min(64) as i32);
+    let base = base.min(self.max.as_secs_f64());
+    let jitter = self.jitter.clamp(0.0, 1.0) * rand::random_range(-1.0..=1.0);
+    Duration::try_from_secs_f64((base * (1.0 + jitter)).max(0.0)).unwrap_or(self.max)
+  }
+}
+
+#[derive(Debug)]
+pub enum ReconnectEvent<CMD> {
+  /// Connected to `host` and the certificate is accepted. `gap` is how long the room was not received since the last connection
+  /// was lost, `None` for the first connection.
+  Connected {
+    host: String,
+    gap: Option<Duration>,
+  },
+  /// The certificate is rejected with the code, usually followed by `Disconnected`
+  AuthFailed(i32),
+  /// The connection is lost or failed to connect, the next attempt is after `retry_in`.
+  /// `attempt` is the number of failures in a row.
+  Disconnected {
+    error: String,
+    attempt: u32,
+    retry_in: Duration,
+  },
+  Command(CMD),
+  /// Popularity in a heartbeat response
+  Popularity(u32),
+  /// A packet that failed to decode, the connection goes on
+  DecodeError(String),
+}
+
+/// Connection of a room that reconnects by itself with [`Backoff`].
+/// Each attempt refreshes the token and starts from the next host of the host list,
+/// the identity of [`RoomAuth`] is only fetched once.
+#[derive(Debug)]
+pub struct ReconnectingConnection<CMD: Cmd = MaybeCommand> {
+  job: JoinHandle<()>,
+  rx: Receiver<ReconnectEvent<CMD>>,
+  stats: ConnectionStats,
+}
+
+impl<CMD: Cmd> ReconnectingConnection<CMD> {
+  /// Starts connecting in the background with the default options, see [`ConnectionBuilder`]
+  pub fn start(client: Client, room_id: u64, backoff: Backoff) -> Self {
+    Self::start_with(
+      client,
+      room_id,
+      backoff,
+      Protocol::Brotli,
+      NetworkConfig::default(),
+      false,
+    )
+  }
+
+  pub(crate) fn start_with(
+    client: Client,
+    room_id: u64,
+    backoff: Backoff,
+    protocol: Protocol,
+    config: NetworkConfig,
+    guest: bool,
+  ) -> Self {
+    let (tx, rx) = mpsc::channel(config.channel_buffer.max(1));
+    let stats = ConnectionStats::default();
+    let options = Options {
+      room_id,
+      backoff,
+      protocol,
+      config,
+      guest,
+    };
+    let job = tokio::spawn(run(client, options, tx, stats.clone()));
+    ReconnectingConnection { job, rx, stats }
+  }
+
+  /// Popularity and time of the last heartbeat response of any connection
+  pub fn last_heartbeat(&self) -> Option<HeartbeatInfo> {
+    *self.stats.last_heartbeat.lock()
+  }
+
+  /// Total size of WebSocket messages received by every connection, before decompression
+  pub fn bytes_received(&self) -> u64 {
+    self.stats.bytes_received.load(Ordering::Relaxed)
+  }
+
+  /// Number of events received but not consumed yet
+  pub fn backlog(&self) -> usize {
+    self.rx.len()
+  }
+}
+
+impl<CMD: Cmd> Stream for ReconnectingConnection<CMD> {
+  type Item = ReconnectEvent<CMD>;
+
+  #[inline]
+  fn poll_next(
+    self: std::pin::Pin<&mut Self>,
+    cx: &mut std::task::Context<'_>,
+  ) -> std::task::Poll<Option<Self::Item>> {
+    self.get_mut().rx.poll_recv(cx)
+  }
+}
+
+impl<CMD: Cmd> Drop for ReconnectingConnection<CMD> {
+  fn drop(&mut self) {
+    self.job.abort();
+  }
+}
+
+struct Options {
+  room_id: u64,
+  backoff: Backoff,
+  protocol: Protocol,
+  config: NetworkConfig,
+  guest: bool,
+}
+
+/// Returns when the receiver is dropped
+async fn run<CMD: Cmd>(
+  client: Client,
+  options: Options,
+  tx: Sender<ReconnectEvent<CMD>>,
+  stats: ConnectionStats,
+) {
+  let Options {
+    room_id,
+    ref backoff,
+    ..
+  } = options;
+  let mut auth: Option<RoomAuth> = None;
+  let mut attempt: u32 = 0;
+  let mut host_offset: usize = 0;
+  let mut lost_at: Option<Instant> = None;
+  loop {
+    let error = match connect::<CMD>(&client, &options, &mut auth, host_offset, &stats).await {
+      Ok((con, host)) => {
+        let mut error = "Connection closed".to_string();
+        let mut connected = false;
+        loop {
+          let next =
+            tokio::time::timeout(POLL_INTERVAL, async { con.write().await.next().await }).await;
+          let event = match next {
+            Ok(Some(ConnectionEvent::Command(cmd))) => ReconnectEvent::Command(cmd),
+            Ok(Some(ConnectionEvent::Connected)) => {
+              connected = true;
+              attempt = 0;
+              ReconnectEvent::Connected {
+                host: host.clone(),
+                gap: lost_at.map(|lost_at| lost_at.elapsed()),
+              }
+            },
+            Ok(Some(ConnectionEvent::AuthFailed(code))) => {
+              error = format!("Certificate rejected with code {code}");
+              ReconnectEvent::AuthFailed(code)
+            },
+            Ok(Some(ConnectionEvent::Closed(reason))) => {
+              error = reason;
+              break;
+            },
+            Ok(Some(ConnectionEvent::Popularity(popular))) => ReconnectEvent::Popularity(popular),
+            Ok(Some(ConnectionEvent::DecodeError(err))) => ReconnectEvent::DecodeError(err),
+            Ok(None) => break,
+            Err(_elapsed) => continue,
+          };
+          if tx.send(event).await.is_err() {
+            return;
+          }
+        }
+        con.write().await.close();
+        if connected {
+          lost_at = Some(Instant::now());
+        }
+        error
+      },
+      Err(err) => format!("{err:#}"),
+    };
+
+    host_offset = host_offset.wrapping_add(1);
+    let retry_in = backoff.delay(attempt);
+    attempt = attempt.saturating_add(1);
+    log::debug!("Room {room_id} disconnected, retry in {retry_in:?}: {error}");
+    let event = ReconnectEvent::Disconnected {
+      error,
+      attempt,
+      retry_in,
+    };
+    if tx.send(event).await.is_err() {
+      return;
+    }
+    tokio::time::sleep(retry_in).await;
+  }
+}
+
+async fn connect<CMD: Cmd>(
+  client: &Client,
+  options: &Options,
+  auth: &mut Option<RoomAuth>,
+  host_offset: usize,
+  stats: &ConnectionStats,
+) -> anyhow::Result<(Arc<RwLock<MessageConnection<CMD>>>, String)> {
+  let auth = match auth {
+    Some(auth) => auth,
+    None => auth.insert(RoomAuth::fetch_as(client, options.room_id, options.guest).await?),
+  };
+  MessageConnection::connect_with_auth(
+    client,
+    auth,
+    host_offset,
+    options.protocol,
+    &options.config,
+    stats.clone(),
+  )
+  .await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn backoff(jitter: f64) -> Backoff {
+    Backoff {
+      jitter,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn delay_grows() {
+    let backoff = backoff(0.0);
+    assert_eq!(backoff.delay(0), Duration::from_secs(1));
+    assert_eq!(backoff.delay(1), Duration::from_secs(2));
+    assert_eq!(backoff.delay(3), Duration::from_secs(8));
+    assert_eq!(backoff.delay(5), Duration::from_secs(32));
+  }
+
+  #[test]
+  fn delay_is_capped() {
+    let backoff = backoff(0.0);
+    assert_eq!(backoff.delay(6), Duration::from_secs(60));
+    assert_eq!(backoff.delay(100), Duration::from_secs(60));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(60));
+  }
+
+  #[test]
+  fn delay_does_not_overflow() {
+    let uncapped = Backoff {
+      max: Duration::MAX,
+      ..backoff(0.0)
+    };
+    assert_eq!(uncapped.delay(0), Duration::from_secs(1));
+    assert_eq!(uncapped.delay(u32::MAX), Duration::MAX);
+
+    let jittered = Backoff {
+      max: Duration::MAX,
+      ..backoff(1.0)
+    };
+    for _ in 0..1000 {
+      jittered.delay(u32::MAX);
+    }
+  }
+
+  #[test]
+  fn delay_with_invalid_multiplier() {
+    for multiplier in [-2.0, f64::NAN] {
+      let backoff = Backoff {
+        multiplier,
+        ..backoff(0.0)
+      };
+      assert_eq!(backoff.delay(0), Duration::from_secs(1));
+      assert_eq!(backoff.delay(3), Duration::from_secs(1));
+    }
+  }
+
+  #[test]
+  fn delay_jitter_bounds() {
+    let jittered = backoff(0.2);
+    let delays: Vec<_> = (0..1000).map(|_| jittered.delay(2)).collect();
+    for delay in &delays {
+      assert!(
+        (3.2..=4.8).contains(&delay.as_secs_f64()),
+        "{delay:?} is out of 4s ± 20%"
+      );
+    }
+    assert!(delays.iter().any(|delay| *delay != delays[0]));
+
+    // Applied after the cap
+    let delay = jittered.delay(100).as_secs_f64();
+    assert!((48.0..=72.0).contains(&delay), "{delay}");
+    // Clamped to 100%
+    let delay = backoff(5.0).delay(0).as_secs_f64();
+    assert!((0.0..=2.0).contains(&delay), "{delay}");
+  }
+}
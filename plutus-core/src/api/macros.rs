@@ -11,7 +11,7 @@ macro_rules! api {
       pub struct $name<'a>(&'a crate::client::Client);
 
       impl<'a> $name<'a> {
-        pub fn new(client: &crate::client::Client) -> $name {
+        pub fn new(client: &crate::client::Client) -> $name<'_> {
           $name(client)
         }
       }
@@ -21,7 +21,7 @@ macro_rules! api_getter {
   ) => {
     $(
       pastey::item! {
-        pub fn [<$api_struct:lower>](&self) -> crate::api::$api_struct {
+        pub fn [<$api_struct:lower>](&self) -> crate::api::$api_struct<'_> {
           crate::api::$api_struct::new(self)
         }
       }
@@ -123,7 +123,7 @@ trait CookiesBiliExt {
   const SESSDATA: &'static str = "SESSDATA";
 
   fn contains_bili(&self, name: &str) -> bool;
-  fn get_bili(&self, name: &str) -> Option<&cookie_store::Cookie>;
+  fn get_bili(&self, name: &str) -> Option<&cookie_store::Cookie<'_>>;
 }
 
 impl CookiesBiliExt for CookieStore {
@@ -133,7 +133,7 @@ impl CookiesBiliExt for CookieStore {
   }
 
   #[inline]
-  fn get_bili(&self, name: &str) -> Option<&cookie_store::Cookie> {
+  fn get_bili(&self, name: &str) -> Option<&cookie_store::Cookie<'_>> {
     self.get(Self::DOMAIN, Self::ROOT, name)
   }
 }
@@ -160,13 +160,21 @@ pub struct WssHost {
 #[allow(dead_code)]
 impl WssHost {
   pub fn to_url(&self) -> Result<reqwest::Url, url::ParseError> {
-    const SCHEMA: &str = "wss://";
+    self.url_of("wss://", self.wss_port)
+  }
+
+  /// Unencrypted `ws://` url on `ws_port`, a fallback if TLS to the host fails
+  pub fn to_ws_url(&self) -> Result<reqwest::Url, url::ParseError> {
+    self.url_of("ws://", self.ws_port)
+  }
+
+  fn url_of(&self, schema: &str, port: u16) -> Result<reqwest::Url, url::ParseError> {
     const PATH: &str = "/sub";
     const SEP: &str = ":";
-    let port = self.wss_port.to_string();
+    let port = port.to_string();
     let mut host =
-      String::with_capacity(SCHEMA.len() + self.host.len() + PATH.len() + SEP.len() + port.len());
-    host.push_str(SCHEMA);
+      String::with_capacity(schema.len() + self.host.len() + PATH.len() + SEP.len() + port.len());
+    host.push_str(schema);
     host.push_str(self.host.as_str());
     host.push_str(SEP);
     host.push_str(port.as_str());
@@ -366,7 +374,7 @@ pub struct Certificate {
   pub protocol: Protocol,
 }
 
-#[derive(Serialize_repr)]
+#[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Protocol {
   Zlib = 2,
@@ -409,7 +417,7 @@ pub struct CertificateResp {
 #[allow(dead_code)]
 impl CertificateResp {
   #[inline]
-  fn is_success(&self) -> bool {
+  pub fn is_success(&self) -> bool {
     self.code == 0
   }
 }
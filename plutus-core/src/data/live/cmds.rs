@@ -56,6 +56,9 @@ impl fmt::Debug for MaybeCommand {
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "cmd", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Command {
+  ComboSend {
+    data: Box<ComboSend>,
+  },
   CutOff {
     #[serde(flatten)]
     data: Box<CutOff>,
@@ -129,6 +132,9 @@ pub enum Command {
     #[serde(flatten)]
     data: Box<RoomShield>,
   },
+  SendGift {
+    data: Box<SendGift>,
+  },
   StopLiveRoomList {
     data: Box<StopLiveRoomList>,
   },
@@ -155,6 +161,35 @@ pub enum Command {
   },
 }
 
+/// 礼物连击
+#[serde_as]
+#[derive(Deserialize, Debug, Clone)]
+pub struct ComboSend {
+  pub uid: u64,
+  #[serde(rename = "uname")]
+  pub username: String,
+  pub action: String,
+  pub gift_id: u64,
+  pub gift_name: String,
+  pub gift_num: u32,
+  pub combo_id: String,
+  pub combo_num: u32,
+  #[serde_as(as = "NoneAsEmptyString")]
+  #[serde(default)]
+  pub batch_combo_id: Option<String>,
+  #[serde(default)]
+  pub batch_combo_num: u32,
+  /// Total value of the whole combo, in gold coins
+  pub combo_total_coin: u64,
+  pub total_num: u32,
+  #[serde(rename = "ruid")]
+  pub liver_uid: u64,
+  #[serde(rename = "r_uname")]
+  pub liver_name: String,
+  #[serde(rename = "medal_info", default)]
+  pub medal: Option<MedalInfo>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CutOff {
   #[serde(rename = "msg")]
@@ -710,6 +745,86 @@ pub struct RoomShield {
   pub keyword: Vec<String>,
 }
 
+/// 投喂礼物
+#[serde_as]
+#[derive(Deserialize, Debug, Clone)]
+pub struct SendGift {
+  pub uid: u64,
+  #[serde(rename = "uname")]
+  pub username: String,
+  #[serde(rename = "face")]
+  pub avatar: Option<String>,
+  pub action: String,
+  #[serde(rename = "giftId")]
+  pub gift_id: u64,
+  #[serde(rename = "giftName")]
+  pub gift_name: String,
+  #[serde(rename = "giftType")]
+  pub gift_type: u32,
+  pub num: u32,
+  pub coin_type: CoinType,
+  /// Price of a single gift, in gold or silver coins according to `coin_type`
+  pub price: u64,
+  pub total_coin: u64,
+  #[serde_as(as = "NoneAsEmptyString")]
+  #[serde(default)]
+  pub batch_combo_id: Option<String>,
+  #[serde(default)]
+  pub batch_combo_send: Option<BatchComboSend>,
+  #[serde(default)]
+  pub combo_send: Option<GiftComboSend>,
+  pub guard_level: GuardLevel,
+  #[serde(rename = "medal_info", default)]
+  pub medal: Option<MedalInfo>,
+  #[serde(deserialize_with = "date_as_unix_ts")]
+  pub timestamp: Option<OffsetDateTime>,
+}
+
+impl SendGift {
+  /// Whether the gift is paid with gold coins (1000 gold coins = 1 CNY)
+  #[inline]
+  pub fn is_paid(&self) -> bool {
+    self.coin_type == CoinType::Gold
+  }
+}
+
+#[derive(Default, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinType {
+  #[serde(rename = "gold")]
+  Gold, // 金瓜子
+  #[serde(rename = "silver")]
+  Silver, // 银瓜子
+  #[default]
+  #[serde(other)]
+  Unknown,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BatchComboSend {
+  pub action: String,
+  pub batch_combo_id: String,
+  pub batch_combo_num: u32,
+  pub gift_id: u64,
+  pub gift_name: String,
+  pub gift_num: u32,
+  pub uid: u64,
+  #[serde(rename = "uname")]
+  pub username: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GiftComboSend {
+  pub action: String,
+  pub combo_id: String,
+  pub combo_num: u32,
+  pub gift_id: u64,
+  pub gift_name: String,
+  pub gift_num: u32,
+  pub uid: u64,
+  #[serde(rename = "uname")]
+  pub username: String,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct StopLiveRoomList {
   #[serde(rename = "room_id_list")]
@@ -929,6 +1044,50 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn de_send_gift() -> anyhow::Result<()> {
+    let command = Command::deserialize(json! {
+      {"cmd":"SEND_GIFT","data":{"action":"投喂","batch_combo_id":"batch:gift:combo_id:1:2:31036:1676986353.1","batch_combo_send":{"action":"投喂","batch_combo_id":"batch:gift:combo_id:1:2:31036:1676986353.1","batch_combo_num":3,"blind_gift":null,"gift_id":31036,"gift_name":"小花花","gift_num":3,"send_master":null,"uid":1,"uname":"user"},"coin_type":"gold","combo_send":null,"face":"https://i0.hdslb.com/bfs/face/member/noface.jpg","giftId":31036,"giftName":"小花花","giftType":5,"guard_level":3,"medal_info":{"anchor_roomid":0,"anchor_uname":"","guard_level":3,"icon_id":0,"is_lighted":1,"medal_color":1725515,"medal_color_border":6809855,"medal_color_end":5414290,"medal_color_start":1725515,"medal_level":21,"medal_name":"牌子","special":"","target_id":2},"num":3,"price":100,"timestamp":1676986353,"total_coin":300,"uid":1,"uname":"user"}}
+    })?;
+    let Command::SendGift { data } = command else {
+      panic!()
+    };
+    assert_eq!(data.gift_id, 31036);
+    assert_eq!(data.coin_type, CoinType::Gold);
+    assert!(data.is_paid());
+    assert_eq!(data.total_coin, 300);
+    assert_eq!(data.batch_combo_send.map(|b| b.batch_combo_num), Some(3));
+    assert!(data.combo_send.is_none());
+    assert_eq!(data.medal.map(|m| m.medal_level), Some(21));
+
+    let command = Command::deserialize(json! {
+      {"cmd":"SEND_GIFT","data":{"action":"投喂","batch_combo_id":"","batch_combo_send":null,"coin_type":"silver","combo_send":null,"face":"","giftId":1,"giftName":"辣条","giftType":0,"guard_level":0,"medal_info":null,"num":1,"price":100,"timestamp":1676986353,"total_coin":100,"uid":1,"uname":"user"}}
+    })?;
+    let Command::SendGift { data } = command else {
+      panic!()
+    };
+    assert_eq!(data.coin_type, CoinType::Silver);
+    assert!(data.batch_combo_id.is_none());
+    assert!(data.medal.is_none());
+
+    Ok(())
+  }
+
+  #[test]
+  fn de_combo_send() -> anyhow::Result<()> {
+    let command = Command::deserialize(json! {
+      {"cmd":"COMBO_SEND","data":{"action":"投喂","batch_combo_id":"batch:gift:combo_id:1:2:31036:1676986353.1","batch_combo_num":5,"combo_id":"gift:combo_id:1:2:31036:1676986353.1","combo_num":5,"combo_total_coin":500,"gift_id":31036,"gift_name":"小花花","gift_num":0,"medal_info":{"anchor_roomid":0,"anchor_uname":"","guard_level":0,"icon_id":0,"is_lighted":0,"medal_color":0,"medal_color_border":0,"medal_color_end":0,"medal_color_start":0,"medal_level":0,"medal_name":"","special":"","target_id":0},"r_uname":"liver","ruid":2,"total_num":5,"uid":1,"uname":"user"}}
+    })?;
+    let Command::ComboSend { data } = command else {
+      panic!()
+    };
+    assert_eq!(data.combo_num, 5);
+    assert_eq!(data.combo_total_coin, 500);
+    assert_eq!(data.liver_uid, 2);
+
+    Ok(())
+  }
+
   #[test]
   fn de_room_silent_time() {
     let time = RoomSilentTime::deserialize(json!(-1)).unwrap();
@@ -0,0 +1,49 @@
+CREATE TABLE IF NOT EXISTS sessions (
+   id               BIGSERIAL    PRIMARY KEY,
+   room_id          BIGINT       NOT NULL,
+   live_key         VARCHAR(128),
+   sub_session_key  VARCHAR(128),
+   platform         VARCHAR(64),
+   start_time       timestamptz  NOT NULL,
+   end_time         timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS sessions_room_id_start_time_idx ON sessions USING BTREE (room_id, start_time);
+-- At most one ongoing session per room
+CREATE UNIQUE INDEX IF NOT EXISTS sessions_room_id_open_idx ON sessions (room_id) WHERE end_time IS NULL;
+
+-- Derive sessions from the LIVE / PREPARING commands collected so far,
+-- repeated LIVE commands with the same live_key belong to the same session.
+WITH events AS (
+  SELECT
+    room_id,
+    command,
+    "time",
+    raw_json->>'live_key' AS live_key,
+    raw_json->>'sub_session_key' AS sub_session_key,
+    raw_json->>'live_platform' AS platform,
+    LAG(command) OVER w AS prev_command,
+    LAG(raw_json->>'live_key') OVER w AS prev_live_key
+  FROM logs
+  WHERE command IN ('LIVE', 'PREPARING')
+  WINDOW w AS (PARTITION BY room_id ORDER BY "time", id)
+), starts AS (
+  SELECT * FROM events
+  WHERE command = 'LIVE'
+    AND (prev_command IS DISTINCT FROM 'LIVE' OR prev_live_key IS DISTINCT FROM live_key)
+)
+INSERT INTO sessions (room_id, live_key, sub_session_key, platform, start_time, end_time)
+SELECT
+  s.room_id,
+  s.live_key,
+  s.sub_session_key,
+  s.platform,
+  s."time",
+  LEAST(
+    (SELECT MIN(e."time") FROM events e
+      WHERE e.room_id = s.room_id AND e.command = 'PREPARING' AND e."time" > s."time"),
+    (SELECT MIN(n."time") FROM starts n
+      WHERE n.room_id = s.room_id AND n."time" > s."time")
+  )
+FROM starts s
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS rooms (
+   room_id          BIGINT       PRIMARY KEY,
+   created_at       timestamptz  NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,12 @@
+DROP TABLE
+  gifts
+  ;
+DROP TABLE
+  guard_buys
+  ;
+DROP TABLE
+  super_chats
+  ;
+DROP TABLE
+  danmaku
+  ;
@@ -0,0 +1,74 @@
+-- Typed rows parsed from the commands at ingest time, the raw log is still kept in `logs`.
+-- `log_id` is the id of the raw log, not a foreign key so `logs` can be pruned independently.
+
+CREATE TABLE IF NOT EXISTS danmaku (
+   log_id           BIGINT       PRIMARY KEY,
+   room_id          BIGINT       NOT NULL,
+   "time"           timestamptz  NOT NULL,
+   uid              BIGINT       NOT NULL,
+   username         VARCHAR(64)  NOT NULL,
+   content          TEXT         NOT NULL,
+   mode             SMALLINT     NOT NULL,
+   is_emoticon      BOOLEAN      NOT NULL,
+   medal_name       VARCHAR(64),
+   medal_level      INT,
+   medal_liver_uid  BIGINT,
+   medal_guard_level SMALLINT
+);
+
+CREATE INDEX IF NOT EXISTS danmaku_room_id_time_idx ON danmaku USING BTREE (room_id, "time");
+CREATE INDEX IF NOT EXISTS danmaku_uid_idx ON danmaku USING HASH (uid);
+
+CREATE TABLE IF NOT EXISTS super_chats (
+   log_id           BIGINT       PRIMARY KEY,
+   room_id          BIGINT       NOT NULL,
+   "time"           timestamptz  NOT NULL,
+   id               BIGINT       NOT NULL,
+   uid              BIGINT       NOT NULL,
+   username         VARCHAR(64)  NOT NULL,
+   -- CNY
+   price            INT          NOT NULL,
+   message          TEXT,
+   translation      TEXT,
+   duration_secs    INT          NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS super_chats_room_id_time_idx ON super_chats USING BTREE (room_id, "time");
+CREATE INDEX IF NOT EXISTS super_chats_room_id_price_idx ON super_chats USING BTREE (room_id, price);
+CREATE INDEX IF NOT EXISTS super_chats_uid_idx ON super_chats USING HASH (uid);
+
+CREATE TABLE IF NOT EXISTS guard_buys (
+   log_id           BIGINT       PRIMARY KEY,
+   room_id          BIGINT       NOT NULL,
+   "time"           timestamptz  NOT NULL,
+   uid              BIGINT       NOT NULL,
+   username         VARCHAR(64)  NOT NULL,
+   guard_level      SMALLINT     NOT NULL,
+   -- Gold coins of a single unit
+   price            BIGINT       NOT NULL,
+   num              INT          NOT NULL,
+   gift_name        VARCHAR(64)
+);
+
+CREATE INDEX IF NOT EXISTS guard_buys_room_id_time_idx ON guard_buys USING BTREE (room_id, "time");
+CREATE INDEX IF NOT EXISTS guard_buys_uid_idx ON guard_buys USING HASH (uid);
+
+CREATE TABLE IF NOT EXISTS gifts (
+   log_id           BIGINT       PRIMARY KEY,
+   room_id          BIGINT       NOT NULL,
+   "time"           timestamptz  NOT NULL,
+   uid              BIGINT       NOT NULL,
+   username         VARCHAR(64)  NOT NULL,
+   gift_id          BIGINT       NOT NULL,
+   gift_name        VARCHAR(64)  NOT NULL,
+   action           VARCHAR(32)  NOT NULL,
+   num              INT          NOT NULL,
+   -- gold, silver or unknown
+   coin_type        VARCHAR(16)  NOT NULL,
+   -- Coins of a single gift
+   price            BIGINT       NOT NULL,
+   total_coin       BIGINT       NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS gifts_room_id_time_idx ON gifts USING BTREE (room_id, "time");
+CREATE INDEX IF NOT EXISTS gifts_uid_idx ON gifts USING HASH (uid);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS
+  super_chats_message_trgm_idx
+  ;
+DROP INDEX IF EXISTS
+  danmaku_content_trgm_idx
+  ;
@@ -0,0 +1,23 @@
+-- Trigram indexes for keyword and regex search of message content.
+-- pg_trgm only takes trigrams from word characters as classified by the LC_CTYPE of the database.
+-- Under a UTF-8 locale, e.g. `C.UTF-8` or `zh_CN.UTF-8`, CJK characters are word characters,
+-- but under `C` or `POSIX` they are dropped, so CJK text gets no trigrams and isn't indexed at all.
+-- Either way, keywords shorter than 3 characters, which are typical of Chinese searches,
+-- have no trigrams, so the index can't narrow them down and every message of the room is scanned.
+--
+-- Creating `pg_trgm` needs the CREATE privilege on the database (PostgreSQL 13+, it is a trusted extension)
+-- and the contrib package installed. Without them the indexes are skipped with a warning,
+-- search still works by scanning. Run this file again as a privileged user to add them later.
+
+DO $$
+BEGIN
+  BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+  EXCEPTION WHEN insufficient_privilege OR undefined_file OR feature_not_supported THEN
+    RAISE WARNING 'Skipped trigram indexes, pg_trgm is unavailable: %', SQLERRM;
+    RETURN;
+  END;
+  CREATE INDEX IF NOT EXISTS danmaku_content_trgm_idx ON danmaku USING GIN (content gin_trgm_ops);
+  CREATE INDEX IF NOT EXISTS super_chats_message_trgm_idx ON super_chats USING GIN (message gin_trgm_ops);
+END;
+$$;
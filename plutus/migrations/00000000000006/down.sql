@@ -0,0 +1,20 @@
+DROP FUNCTION
+  create_logs_partition
+  ;
+
+ALTER TABLE logs DETACH PARTITION logs_legacy;
+INSERT INTO logs_legacy SELECT * FROM logs;
+ALTER SEQUENCE logs_id_seq OWNED BY logs_legacy.id;
+DROP TABLE
+  logs
+  ;
+
+ALTER TABLE logs_legacy RENAME TO logs;
+DROP INDEX IF EXISTS logs_legacy_room_id_time_idx;
+DROP INDEX IF EXISTS logs_legacy_command_time_idx;
+ALTER TABLE logs DROP CONSTRAINT logs_legacy_pkey;
+ALTER TABLE logs ADD CONSTRAINT logs_pkey PRIMARY KEY (id);
+ALTER INDEX logs_legacy_related_uid_idx RENAME TO logs_related_uid_idx;
+CREATE INDEX IF NOT EXISTS logs_command_idx ON logs USING HASH (command);
+CREATE INDEX IF NOT EXISTS logs_room_id_idx ON logs USING HASH (room_id);
+CREATE INDEX IF NOT EXISTS logs_time_idx ON logs USING BTREE (room_id);
@@ -0,0 +1,91 @@
+-- Partitions `logs` by month of "time", so months past the retention can be dropped as a whole.
+-- The existing table is attached as the partition of everything before next month, rows are not copied.
+--
+-- Locking: the migration is one transaction holding ACCESS EXCLUSIVE on the existing table until it commits,
+-- the server starts once it is done. Work proportional to the existing rows:
+--   * rebuilding the primary key as (id, "time"),
+--   * one scan validating the time bound, so ATTACH PARTITION doesn't scan again,
+--   * building the (room_id, "time") and (command, "time") indexes.
+-- It can take long on a large table, plan the upgrade accordingly.
+
+ALTER TABLE logs RENAME TO logs_legacy;
+-- Superseded by the indexes of the partitioned table below
+DROP INDEX logs_command_idx;
+DROP INDEX logs_room_id_idx;
+DROP INDEX logs_time_idx;
+-- Matches the index of the partitioned table, so it is attached instead of built again
+ALTER INDEX logs_related_uid_idx RENAME TO logs_legacy_related_uid_idx;
+-- The primary key of a partitioned table must include the partition key
+ALTER TABLE logs_legacy DROP CONSTRAINT logs_pkey;
+ALTER TABLE logs_legacy ADD CONSTRAINT logs_legacy_pkey PRIMARY KEY (id, "time");
+
+CREATE TABLE logs (
+   id               BIGINT       NOT NULL DEFAULT nextval('logs_id_seq'),
+   room_id          BIGINT       NOT NULL,
+   command          VARCHAR(128) NOT NULL,
+   raw_json         JSONB        NOT NULL,
+   "time"           timestamptz  NOT NULL,
+   related_uid      BIGINT,
+   PRIMARY KEY (id, "time")
+) PARTITION BY RANGE ("time");
+
+ALTER SEQUENCE logs_id_seq OWNED BY logs.id;
+
+-- Monthly partitions start from the month after the legacy rows
+DO $$
+DECLARE
+  legacy_end timestamptz;
+BEGIN
+  SELECT (date_trunc('month', GREATEST(NOW(), MAX("time")) AT TIME ZONE 'UTC') + INTERVAL '1 month')
+    AT TIME ZONE 'UTC'
+  INTO legacy_end
+  FROM logs_legacy;
+  -- A validated CHECK implying the partition bound lets ATTACH PARTITION skip its own scan
+  EXECUTE format(
+    'ALTER TABLE logs_legacy ADD CONSTRAINT logs_legacy_time_check CHECK ("time" < %L) NOT VALID',
+    legacy_end
+  );
+  ALTER TABLE logs_legacy VALIDATE CONSTRAINT logs_legacy_time_check;
+  EXECUTE format(
+    'ALTER TABLE logs ATTACH PARTITION logs_legacy FOR VALUES FROM (MINVALUE) TO (%L)',
+    legacy_end
+  );
+  ALTER TABLE logs_legacy DROP CONSTRAINT logs_legacy_time_check;
+END;
+$$;
+-- Only used if a monthly partition is missing
+CREATE TABLE logs_default PARTITION OF logs DEFAULT;
+
+CREATE INDEX logs_room_id_time_idx ON logs USING BTREE (room_id, "time");
+-- Used by pruning
+CREATE INDEX logs_command_time_idx ON logs USING BTREE (command, "time");
+CREATE INDEX logs_related_uid_idx ON logs USING HASH (related_uid);
+
+-- Creates the partition of the month starting at `month_start` in UTC, named like `logs_y2024m01`.
+-- Rows of the month in the default partition are moved into it.
+-- Returns false if it exists or overlaps the legacy partition.
+CREATE OR REPLACE FUNCTION create_logs_partition(month_start timestamptz) RETURNS boolean AS $$
+DECLARE
+  start_utc timestamp := date_trunc('month', month_start AT TIME ZONE 'UTC');
+  name text := 'logs_' || to_char(start_utc, '"y"YYYY"m"MM');
+  range_start timestamptz := start_utc AT TIME ZONE 'UTC';
+  range_end timestamptz := (start_utc + INTERVAL '1 month') AT TIME ZONE 'UTC';
+BEGIN
+  IF to_regclass(name) IS NOT NULL THEN
+    RETURN false;
+  END IF;
+  EXECUTE format('CREATE TABLE %I (LIKE logs INCLUDING DEFAULTS)', name);
+  EXECUTE format(
+    'WITH moved AS (DELETE FROM logs_default WHERE "time" >= %L AND "time" < %L RETURNING *)
+     INSERT INTO %I SELECT * FROM moved',
+    range_start, range_end, name
+  );
+  EXECUTE format(
+    'ALTER TABLE logs ATTACH PARTITION %I FOR VALUES FROM (%L) TO (%L)',
+    name, range_start, range_end
+  );
+  RETURN true;
+EXCEPTION WHEN invalid_object_definition THEN
+  RETURN false;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS logs_room_id_time_idx ON logs USING BTREE (room_id, "time");
+DROP INDEX IF EXISTS logs_room_id_time_id_idx;
@@ -0,0 +1,4 @@
+-- Index of keyset pagination by `(time, id) > (t, i)` in a room, replacing `(room_id, "time")`.
+-- Built on every partition of `logs`, blocking writes to it until done.
+CREATE INDEX IF NOT EXISTS logs_room_id_time_id_idx ON logs USING BTREE (room_id, "time", id);
+DROP INDEX IF EXISTS logs_room_id_time_idx;
@@ -0,0 +1,2 @@
+DELETE FROM rooms WHERE removed_at IS NOT NULL;
+ALTER TABLE rooms DROP COLUMN IF EXISTS removed_at;
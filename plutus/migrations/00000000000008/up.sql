@@ -0,0 +1,3 @@
+-- Rooms removed by `DELETE /rooms/{id}` are kept with `removed_at`,
+-- so rooms in config are only saved once and stay removed after a restart.
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS removed_at timestamptz;
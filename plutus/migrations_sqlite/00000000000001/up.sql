@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS logs (
+   id               INTEGER      PRIMARY KEY AUTOINCREMENT,
+   room_id          BIGINT       NOT NULL,
+   command          VARCHAR(128) NOT NULL,
+   raw_json         TEXT         NOT NULL,
+   "time"           TEXT         NOT NULL,
+   related_uid      BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS logs_room_id_time_idx ON logs (room_id, "time");
+CREATE INDEX IF NOT EXISTS logs_command_idx ON logs (command);
+CREATE INDEX IF NOT EXISTS logs_related_uid_idx ON logs (related_uid);
@@ -1,8 +1,9 @@
 use std::{
+  collections::HashMap,
   fs::File,
   io::{BufReader, Read},
   net::SocketAddr,
-  path::Path,
+  path::{Path, PathBuf},
   str::FromStr,
 };
 
@@ -14,8 +15,102 @@ use serde::Deserialize;
 pub struct Config {
   #[serde(alias = "addr", default = "Config::default_address")]
   pub address: SocketAddr,
+  /// PostgreSQL url, `sqlite://path/to/plutus.db` to archive logs in SQLite,
+  /// or `memory:` to keep logs in memory only
   pub database_url: String,
+  /// Always collected, more rooms can be added at runtime via `POST /rooms`
+  #[serde(default)]
   pub rooms: Vec<u64>,
+  /// Connects rooms as a guest with uid 0 instead of logging in.
+  /// Bilibili masks usernames in the commands received by guests.
+  #[serde(default)]
+  pub guest: bool,
+  /// Capacity of the live feed channel, slow subscribers skip messages beyond it. 0 is taken as 1
+  #[serde(default = "Config::default_feed_buffer")]
+  pub feed_buffer: usize,
+  #[serde(default)]
+  pub writer: WriterConfig,
+  #[serde(default)]
+  pub retention: RetentionConfig,
+  #[serde(default)]
+  pub ingest: IngestConfig,
+}
+
+/// Collected logs are queued and inserted in batches, see [`crate::writer`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct WriterConfig {
+  /// Capacity of the queue, collectors wait when it is full
+  pub queue_size: usize,
+  /// Maximum rows of a single INSERT, at most 6553
+  pub batch_size: usize,
+  /// A batch is flushed after this many milliseconds even if it is not full
+  pub flush_interval_ms: u64,
+  /// Logs are dropped if the queue is still full after this many milliseconds
+  pub enqueue_timeout_ms: u64,
+  /// Logs are saved here while the database is unavailable, and replayed once it is back
+  pub spool_dir: PathBuf,
+}
+
+impl Default for WriterConfig {
+  fn default() -> Self {
+    WriterConfig {
+      queue_size: 16384,
+      batch_size: 1000,
+      flush_interval_ms: 500,
+      enqueue_timeout_ms: 1000,
+      spool_dir: PathBuf::from("spool"),
+    }
+  }
+}
+
+/// Logs older than their retention are pruned periodically, see [`crate::retention`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct RetentionConfig {
+  /// Days to keep commands not listed in `commands`, forever if not set
+  pub default_days: Option<u32>,
+  /// Days to keep each command, e.g. `ONLINE_RANK_COUNT = 7`
+  pub commands: HashMap<String, u32>,
+  /// How often logs are pruned, monthly partitions are created at the same time
+  pub interval_secs: u64,
+  /// Maximum rows deleted by a single statement
+  pub batch_size: i64,
+}
+
+impl Default for RetentionConfig {
+  fn default() -> Self {
+    RetentionConfig {
+      default_days: None,
+      commands: HashMap::new(),
+      interval_secs: 3600,
+      batch_size: 10000,
+    }
+  }
+}
+
+/// Commands saved by the collectors, see [`crate::ingest`].
+/// Every command is still published to the feed.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct IngestConfig {
+  /// Rules of every room
+  #[serde(flatten)]
+  pub global: CommandFilter,
+  /// Rules of a room, applied along with the global rules, e.g. `[ingest.rooms.21452505]`.
+  /// Keys are strings as TOML keys are never integers.
+  #[serde(default)]
+  pub rooms: HashMap<String, CommandFilter>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct CommandFilter {
+  /// Only these commands are saved if not empty
+  pub include: Vec<String>,
+  pub exclude: Vec<String>,
+  /// Saves one of every N logs of the command, e.g. `LIKE_INFO_V3_UPDATE = 10`
+  pub sample: HashMap<String, u32>,
 }
 
 impl Config {
@@ -23,6 +118,10 @@ impl Config {
     SocketAddr::from_str("127.0.0.1:7727").unwrap()
   }
 
+  fn default_feed_buffer() -> usize {
+    1024
+  }
+
   pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
     let path = path.as_ref();
     let file = File::open(path)
@@ -0,0 +1,430 @@
+use std::{
+  fs::File,
+  io::{stdout, Write},
+  path::PathBuf,
+  sync::Arc,
+};
+
+use anyhow::{anyhow, Context};
+use arrow_array::{
+  ArrayRef, Int16Array, Int32Array, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use axum::{
+  body::Body,
+  extract::Query,
+  http::header,
+  response::{IntoResponse, Response},
+  Json,
+};
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use futures_util::stream;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use plutus_core::data::live::cmds::MaybeCommand;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  error::AppResult,
+  global_state,
+  models::Log,
+  routes::{check_text_search, QueryBody, TimeRange},
+  sessions,
+  state::AsyncPoolConnection,
+  store::{LogFilter, LogKey},
+  typed::TypedRow,
+};
+
+/// Logs read from the store per query, also the row group size of Parquet
+const EXPORT_CHUNK_SIZE: i64 = 5000;
+
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+  /// One log per line, same as `/list`
+  #[default]
+  Ndjson,
+  /// Typed fields flattened into columns
+  Csv,
+  /// Same columns as CSV
+  Parquet,
+}
+
+impl ExportFormat {
+  fn extension(self) -> &'static str {
+    match self {
+      ExportFormat::Ndjson => "ndjson",
+      ExportFormat::Csv => "csv",
+      ExportFormat::Parquet => "parquet",
+    }
+  }
+
+  fn content_type(self) -> &'static str {
+    match self {
+      ExportFormat::Ndjson => "application/x-ndjson",
+      ExportFormat::Csv => "text/csv; charset=utf-8",
+      ExportFormat::Parquet => "application/vnd.apache.parquet",
+    }
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportParams {
+  #[serde(default)]
+  pub format: ExportFormat,
+}
+
+/// A log with fields of its typed row flattened, columns of CSV and Parquet
+#[derive(Serialize, Debug, Default)]
+struct ExportRow {
+  id: i64,
+  room_id: i64,
+  command: String,
+  time: DateTime<Utc>,
+  related_uid: Option<i64>,
+  username: Option<String>,
+  /// Danmaku content or SuperChat message
+  content: Option<String>,
+  /// CNY of SuperChat, coins of a single unit otherwise
+  price: Option<i64>,
+  num: Option<i32>,
+  guard_level: Option<i16>,
+  gift_name: Option<String>,
+  coin_type: Option<String>,
+  medal_name: Option<String>,
+  medal_level: Option<i32>,
+  raw_json: String,
+}
+
+impl ExportRow {
+  fn new(log: Log) -> ExportRow {
+    let mut row = ExportRow {
+      raw_json: log.raw_json.to_string(),
+      id: log.id,
+      room_id: log.room_id,
+      time: log.time,
+      related_uid: log.related_uid,
+      ..Default::default()
+    };
+    let typed = match serde_json::from_value::<MaybeCommand>(log.raw_json) {
+      Ok(MaybeCommand::Command(cmd)) => TypedRow::from_command(&cmd),
+      _ => None,
+    };
+    match typed {
+      Some(TypedRow::Danmaku(danmaku)) => {
+        row.username = Some(danmaku.username);
+        row.content = Some(danmaku.content);
+        row.medal_name = danmaku.medal_name;
+        row.medal_level = danmaku.medal_level;
+      },
+      Some(TypedRow::SuperChat(super_chat)) => {
+        row.username = Some(super_chat.username);
+        row.content = super_chat.message;
+        row.price = Some(super_chat.price as i64);
+      },
+      Some(TypedRow::GuardBuy(guard_buy)) => {
+        row.username = Some(guard_buy.username);
+        row.price = Some(guard_buy.price);
+        row.num = Some(guard_buy.num);
+        row.guard_level = Some(guard_buy.guard_level);
+        row.gift_name = guard_buy.gift_name;
+      },
+      Some(TypedRow::Gift(gift)) => {
+        row.username = Some(gift.username);
+        row.price = Some(gift.price);
+        row.num = Some(gift.num);
+        row.gift_name = Some(gift.gift_name);
+        row.coin_type = Some(gift.coin_type);
+      },
+      None => {},
+    }
+    row.command = log.command;
+    row
+  }
+
+  fn schema() -> SchemaRef {
+    let utc = Some("UTC".into());
+    Arc::new(Schema::new(vec![
+      Field::new("id", DataType::Int64, false),
+      Field::new("room_id", DataType::Int64, false),
+      Field::new("command", DataType::Utf8, false),
+      Field::new(
+        "time",
+        DataType::Timestamp(TimeUnit::Microsecond, utc),
+        false,
+      ),
+      Field::new("related_uid", DataType::Int64, true),
+      Field::new("username", DataType::Utf8, true),
+      Field::new("content", DataType::Utf8, true),
+      Field::new("price", DataType::Int64, true),
+      Field::new("num", DataType::Int32, true),
+      Field::new("guard_level", DataType::Int16, true),
+      Field::new("gift_name", DataType::Utf8, true),
+      Field::new("coin_type", DataType::Utf8, true),
+      Field::new("medal_name", DataType::Utf8, true),
+      Field::new("medal_level", DataType::Int32, true),
+      Field::new("raw_json", DataType::Utf8, false),
+    ]))
+  }
+
+  fn record_batch(rows: &[ExportRow]) -> anyhow::Result<RecordBatch> {
+    let strings = |get: fn(&ExportRow) -> Option<&str>| -> ArrayRef {
+      Arc::new(StringArray::from(rows.iter().map(get).collect::<Vec<_>>()))
+    };
+    let columns: Vec<ArrayRef> = vec![
+      Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.id))),
+      Arc::new(Int64Array::from_iter_values(
+        rows.iter().map(|row| row.room_id),
+      )),
+      strings(|row| Some(&row.command)),
+      Arc::new(
+        TimestampMicrosecondArray::from_iter_values(
+          rows.iter().map(|row| row.time.timestamp_micros()),
+        )
+        .with_timezone("UTC"),
+      ),
+      Arc::new(Int64Array::from_iter(
+        rows.iter().map(|row| row.related_uid),
+      )),
+      strings(|row| row.username.as_deref()),
+      strings(|row| row.content.as_deref()),
+      Arc::new(Int64Array::from_iter(rows.iter().map(|row| row.price))),
+      Arc::new(Int32Array::from_iter(rows.iter().map(|row| row.num))),
+      Arc::new(Int16Array::from_iter(
+        rows.iter().map(|row| row.guard_level),
+      )),
+      strings(|row| row.gift_name.as_deref()),
+      strings(|row| row.coin_type.as_deref()),
+      strings(|row| row.medal_name.as_deref()),
+      Arc::new(Int32Array::from_iter(
+        rows.iter().map(|row| row.medal_level),
+      )),
+      strings(|row| Some(&row.raw_json)),
+    ];
+    Ok(RecordBatch::try_new(ExportRow::schema(), columns)?)
+  }
+}
+
+enum Encoder {
+  Ndjson,
+  Csv { header_written: bool },
+  Parquet(Option<Box<ArrowWriter<Vec<u8>>>>),
+}
+
+impl Encoder {
+  fn new(format: ExportFormat) -> anyhow::Result<Encoder> {
+    let encoder = match format {
+      ExportFormat::Ndjson => Encoder::Ndjson,
+      ExportFormat::Csv => Encoder::Csv {
+        header_written: false,
+      },
+      ExportFormat::Parquet => {
+        let props = WriterProperties::builder()
+          .set_compression(Compression::SNAPPY)
+          .build();
+        let writer = ArrowWriter::try_new(Vec::new(), ExportRow::schema(), Some(props))
+          .context("Failed to create Parquet writer")?;
+        Encoder::Parquet(Some(Box::new(writer)))
+      },
+    };
+    Ok(encoder)
+  }
+
+  fn encode(&mut self, logs: Vec<Log>) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match self {
+      Encoder::Ndjson => {
+        for log in logs {
+          serde_json::to_writer(&mut buf, &log)?;
+          buf.push(b'\n');
+        }
+      },
+      Encoder::Csv { header_written } => {
+        let mut writer = csv::WriterBuilder::new()
+          .has_headers(!*header_written)
+          .from_writer(&mut buf);
+        for log in logs {
+          writer.serialize(ExportRow::new(log))?;
+        }
+        writer.flush()?;
+        *header_written = true;
+      },
+      Encoder::Parquet(writer) => {
+        let writer = writer.as_mut().context("Parquet writer is closed")?;
+        let rows: Vec<ExportRow> = logs.into_iter().map(ExportRow::new).collect();
+        writer.write(&ExportRow::record_batch(&rows)?)?;
+        // A row group per chunk, so the encoded bytes can be sent right away
+        writer.flush()?;
+        buf = std::mem::take(writer.inner_mut());
+      },
+    }
+    Ok(buf)
+  }
+
+  /// Trailing bytes after the last chunk, i.e. the footer of Parquet
+  fn finish(&mut self) -> anyhow::Result<Vec<u8>> {
+    match self {
+      Encoder::Parquet(writer) => match writer.take() {
+        Some(writer) => Ok((*writer).into_inner()?),
+        None => Ok(Vec::new()),
+      },
+      Encoder::Ndjson | Encoder::Csv { .. } => Ok(Vec::new()),
+    }
+  }
+}
+
+struct ExportState {
+  filter: LogFilter,
+  after: Option<LogKey>,
+  encoder: Encoder,
+  done: bool,
+}
+
+/// Streams all logs matching the filter in `(time, id)` order, `cursor` and `count` are ignored.
+/// Logs are read by keyset in chunks, so the result set is never held in memory as a whole.
+pub async fn export(
+  Query(params): Query<ExportParams>,
+  Json(body): Json<QueryBody>,
+) -> AppResult<Response> {
+  let session_range = match body.session {
+    Some(session_id) => {
+      let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+      Some(sessions::time_range(conn, body.room_id, session_id).await?)
+    },
+    None => None,
+  };
+  let filter = LogFilter::new(&body, session_range.as_ref());
+  check_text_search(global_state(), &filter).await?;
+
+  let state = ExportState {
+    filter,
+    after: None,
+    encoder: Encoder::new(params.format)?,
+    done: false,
+  };
+  let chunks = stream::try_unfold(state, |mut state| async move {
+    if state.done {
+      return Ok::<_, anyhow::Error>(None);
+    }
+    let logs = global_state()
+      .store()
+      .list(&state.filter, state.after.as_ref(), 0, EXPORT_CHUNK_SIZE)
+      .await
+      .context("Failed to query logs")
+      .inspect_err(|err| log::error!("Export aborted: {err:?}"))?;
+    state.after = logs.last().map(LogKey::of).or(state.after);
+    state.done = (logs.len() as i64) < EXPORT_CHUNK_SIZE;
+    let mut buf = match logs.is_empty() {
+      true => Vec::new(),
+      false => state.encoder.encode(logs)?,
+    };
+    if state.done {
+      buf.extend(state.encoder.finish()?);
+    }
+    Ok(Some((buf, state)))
+  });
+
+  let filename = format!("{}.{}", body.room_id, params.format.extension());
+  Ok(
+    (
+      [
+        (
+          header::CONTENT_TYPE,
+          params.format.content_type().to_string(),
+        ),
+        (
+          header::CONTENT_DISPOSITION,
+          format!("attachment; filename=\"{filename}\""),
+        ),
+      ],
+      Body::from_stream(chunks),
+    )
+      .into_response(),
+  )
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportCommand {
+  /// Should be real room id
+  #[arg(short, long)]
+  pub room: u64,
+  /// Filter specific UID
+  #[arg(short, long)]
+  pub uid: Option<u64>,
+  /// Commands, e.g. "DANMU_MSG", "SUPER_CHAT_MESSAGE", "GUARD_BUY"
+  #[clap(short, long, value_delimiter = ' ', num_args = 1..)]
+  pub commands: Vec<String>,
+  /// Only export logs during a live session, see `/rooms/{id}/sessions`
+  #[clap(long)]
+  pub session: Option<i64>,
+  /// Only danmaku and SuperChat containing the keyword, case-insensitive
+  #[clap(short, long)]
+  pub keyword: Option<String>,
+  /// Only danmaku and SuperChat matching the POSIX regular expression
+  #[clap(long)]
+  pub regex: Option<String>,
+  /// Only export logs after a certain date, parse using ISO 8601 yyyy-mm-ddThh:mm:ss
+  #[clap(long, value_parser = crate::parse_date)]
+  pub start: Option<DateTime<Utc>>,
+  /// Only export logs before a certain date
+  #[clap(long, value_parser = crate::parse_date)]
+  pub end: Option<DateTime<Utc>>,
+
+  #[clap(short, long, value_enum, default_value = "ndjson")]
+  pub format: ExportFormat,
+  /// Writes to stdout if not set
+  #[clap(short, long)]
+  pub output: Option<PathBuf>,
+
+  #[clap(short, long, default_value = "http://127.0.0.1:7727")]
+  pub server: String,
+}
+
+/// Downloads `/export` of the server chunk by chunk
+pub async fn export_to(args: ExportCommand) -> anyhow::Result<()> {
+  let host = crate::with_http_schema(crate::guess_addr_from_config().unwrap_or(args.server));
+  let format = serde_json::to_value(args.format)?;
+  let format = format.as_str().context("Invalid format")?;
+  let mut resp = reqwest::Client::new()
+    .post(format!("{host}/export?format={format}"))
+    .json(&QueryBody {
+      room_id: args.room,
+      commands: args.commands,
+      uid: args.uid,
+      time_range: Some(TimeRange {
+        start: args.start,
+        end: args.end,
+      }),
+      session: args.session,
+      count: false,
+      keyword: args.keyword,
+      regex: args.regex,
+      cursor: Default::default(),
+    })
+    .send()
+    .await
+    .context("Failed to request export")?;
+  if !resp.status().is_success() {
+    let status = resp.status();
+    let text = resp.text().await.context("Failed to parse body as text")?;
+    return Err(anyhow!("Failed to export: {status}, {text}"));
+  }
+
+  let mut output: Box<dyn Write> = match args.output {
+    Some(ref path) => Box::new(
+      File::create(path)
+        .with_context(|| format!("Failed to create `{}`", path.to_string_lossy()))?,
+    ),
+    None => Box::new(stdout().lock()),
+  };
+  let mut written = 0;
+  while let Some(chunk) = resp.chunk().await.context("Export is interrupted")? {
+    output.write_all(&chunk).context("Failed to write output")?;
+    written += chunk.len();
+  }
+  output.flush().context("Failed to write output")?;
+  if let Some(path) = args.output {
+    log::info!("Exported {written} bytes to `{}`", path.to_string_lossy());
+  }
+  Ok(())
+}
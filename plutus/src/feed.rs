@@ -0,0 +1,121 @@
+use std::{convert::Infallible, future::ready, sync::Arc};
+
+use axum::{
+  extract::{
+    ws::{Message, WebSocket, WebSocketUpgrade},
+    Query,
+  },
+  response::{
+    sse::{Event, KeepAlive, Sse},
+    Response,
+  },
+};
+use futures_core::Stream;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{global_state, models::NewLog};
+
+/// Query string of the live feed endpoints, e.g.
+/// `/feed/sse?room_id=1&commands=DANMU_MSG,SUPER_CHAT_MESSAGE&uid=2`
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct FeedFilter {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub room_id: Option<u64>,
+  /// Comma separated command names
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub commands: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub uid: Option<u64>,
+}
+
+pub struct Filter {
+  room_id: Option<i64>,
+  commands: Vec<String>,
+  uid: Option<i64>,
+}
+
+impl From<FeedFilter> for Filter {
+  fn from(filter: FeedFilter) -> Self {
+    Filter {
+      room_id: filter.room_id.map(|id| id as i64),
+      commands: filter
+        .commands
+        .iter()
+        .flat_map(|commands| commands.split(','))
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(String::from)
+        .collect(),
+      uid: filter.uid.map(|uid| uid as i64),
+    }
+  }
+}
+
+impl Filter {
+  pub fn matches(&self, log: &NewLog) -> bool {
+    self.room_id.is_none_or(|room_id| room_id == log.room_id)
+      && (self.commands.is_empty() || self.commands.contains(&log.command))
+      && self.uid.is_none_or(|uid| Some(uid) == log.related_uid)
+  }
+}
+
+fn subscribe(filter: Filter) -> impl Stream<Item = Arc<NewLog>> {
+  let rx = global_state().subscribe();
+  stream::unfold(rx, |mut rx| async move {
+    loop {
+      match rx.recv().await {
+        Ok(log) => return Some((log, rx)),
+        Err(RecvError::Lagged(skipped)) => {
+          log::warn!("Feed subscriber is too slow, {skipped} messages skipped");
+        },
+        Err(RecvError::Closed) => return None,
+      }
+    }
+  })
+  .filter(move |log| ready(filter.matches(log)))
+}
+
+pub async fn sse(
+  Query(filter): Query<FeedFilter>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+  let stream = subscribe(filter.into()).map(|log| {
+    Ok(
+      Event::default()
+        .event(&log.command)
+        .json_data(&*log)
+        .unwrap_or_else(|err| Event::default().comment(format!("Failed to serialize: {err}"))),
+    )
+  });
+  Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn ws(ws: WebSocketUpgrade, Query(filter): Query<FeedFilter>) -> Response {
+  ws.on_upgrade(move |socket| ws_feed(socket, filter.into()))
+}
+
+async fn ws_feed(mut socket: WebSocket, filter: Filter) {
+  let mut stream = Box::pin(subscribe(filter));
+  loop {
+    tokio::select! {
+      Some(log) = stream.next() => {
+        let json = match serde_json::to_string(&*log) {
+          Ok(json) => json,
+          Err(err) => {
+            log::error!("Failed to serialize {log:?}: {err:?}");
+            continue;
+          },
+        };
+        if socket.send(Message::Text(json.into())).await.is_err() {
+          break;
+        }
+      },
+      msg = socket.recv() => match msg {
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+        Some(Ok(_)) => {},
+      },
+      else => break,
+    }
+  }
+}
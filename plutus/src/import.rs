@@ -0,0 +1,352 @@
+use std::{
+  fs::File,
+  hash::{BuildHasher, Hash, Hasher},
+  io::{stdin, BufRead, BufReader},
+  path::PathBuf,
+};
+
+use ahash::{AHashMap, AHashSet, RandomState};
+use anyhow::{bail, Context};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use clap::Parser;
+use plutus_core::data::live::cmds::{Command, MaybeCommand};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+  error::AnyhowWrapper,
+  global_state,
+  metrics::Metrics,
+  models::{Log, NewLog},
+  related_uid, sessions,
+  state::{AsyncPoolConnection, State},
+  writer::LogWriter,
+  GLOBAL_STATE, METRICS,
+};
+
+/// Records validated and deduplicated at once
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Parser, Debug)]
+pub struct ImportCommand {
+  /// NDJSON files, reads stdin if none is given
+  pub files: Vec<PathBuf>,
+  /// Room id of records without one
+  #[arg(short, long)]
+  pub room: Option<u64>,
+  /// Adds sessions found in the time ranges of the archives after importing.
+  /// Imported `LIVE` and `PREPARING` logs don't open or close sessions otherwise.
+  #[arg(long)]
+  pub sessions: bool,
+}
+
+/// A line of the archive. Exports and spools of Plutus have these fields already,
+/// common names of other recorders are accepted as aliases.
+#[derive(Deserialize, Debug)]
+struct ImportRecord {
+  #[serde(default, alias = "roomid", alias = "room")]
+  room_id: Option<i64>,
+  #[serde(alias = "timestamp", alias = "ts")]
+  time: ImportTime,
+  #[serde(alias = "raw", alias = "message")]
+  raw_json: Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ImportTime {
+  /// RFC 3339
+  Date(DateTime<Utc>),
+  /// Unix timestamp in seconds, or milliseconds if it is too large for seconds
+  Unix(i64),
+}
+
+impl ImportTime {
+  /// Truncated to microseconds, the precision saved by PostgreSQL
+  fn to_utc(&self) -> Option<DateTime<Utc>> {
+    let time = match *self {
+      ImportTime::Date(time) => time,
+      ImportTime::Unix(ts) if ts.abs() < 100_000_000_000 => DateTime::from_timestamp(ts, 0)?,
+      ImportTime::Unix(ts) => DateTime::from_timestamp_millis(ts)?,
+    };
+    time.duration_trunc(TimeDelta::microseconds(1)).ok()
+  }
+}
+
+#[derive(Debug, Default)]
+struct ImportStats {
+  imported: u64,
+  unknown: u64,
+  duplicated: u64,
+  invalid: u64,
+}
+
+/// Imports archived logs through the writer, the same path as collected logs.
+/// Records already saved, or repeated in the archives, are skipped.
+/// The import is aborted once the database is unavailable instead of spooling,
+/// as the spool belongs to the server, and it can be run again to import the rest.
+pub async fn import(args: ImportCommand) -> anyhow::Result<()> {
+  let state = State::init().await.context("Failed to init plutus")?;
+  unsafe {
+    GLOBAL_STATE = Some(state.clone());
+    METRICS = Some(Metrics::new().context("Failed to init metrics")?);
+  }
+  let writer = LogWriter::start_without_spool(&state.config.writer);
+
+  let mut importer = Importer {
+    room_id: args.room.map(|room| room as i64),
+    writer,
+    dedup: Dedup::default(),
+    ranges: AHashMap::new(),
+    batch: Vec::with_capacity(IMPORT_BATCH_SIZE),
+    stats: ImportStats::default(),
+  };
+  let result = importer.read_all(&args.files).await;
+
+  let Importer {
+    writer,
+    stats,
+    ranges,
+    ..
+  } = importer;
+  // Reading fails as well once the writer is stopped, which tells why
+  writer
+    .close()
+    .await
+    .context("Import is aborted, run it again to import the rest")?;
+  result?;
+  log::info!(
+    "Imported {} logs ({} of unknown commands), skipped {} duplicated and {} invalid records",
+    stats.imported,
+    stats.unknown,
+    stats.duplicated,
+    stats.invalid
+  );
+  if args.sessions {
+    rebuild_sessions(ranges).await?;
+  }
+  Ok(())
+}
+
+async fn rebuild_sessions(
+  ranges: AHashMap<i64, (DateTime<Utc>, DateTime<Utc>)>,
+) -> anyhow::Result<()> {
+  if !global_state().is_postgres() {
+    log::warn!("Sessions are only kept in PostgreSQL, skipped rebuilding");
+    return Ok(());
+  }
+  let conn: &mut AsyncPoolConnection = &mut global_state()
+    .db_con()
+    .await
+    .map_err(AnyhowWrapper::into_inner)?;
+  for (room_id, (start, end)) in ranges {
+    let added = sessions::rebuild(conn, room_id, start, end).await?;
+    log::info!("Added {added} sessions of room {room_id} from {start} to {end}");
+  }
+  Ok(())
+}
+
+/// Hashes of logs imported or found saved, to skip duplicates
+#[derive(Default)]
+struct Dedup {
+  seen: AHashSet<u64>,
+  hasher: RandomState,
+}
+
+impl Dedup {
+  fn hash(&self, room_id: i64, command: &str, time: DateTime<Utc>, raw_json: &Value) -> u64 {
+    let mut hasher = self.hasher.build_hasher();
+    room_id.hash(&mut hasher);
+    command.hash(&mut hasher);
+    time.hash(&mut hasher);
+    // Keys are sorted by serde_json, so the same object is always serialized the same
+    raw_json.to_string().hash(&mut hasher);
+    hasher.finish()
+  }
+
+  /// Whether the log is seen for the first time
+  fn insert(&mut self, log: &NewLog) -> bool {
+    let hash = self.hash(log.room_id, &log.command, log.time, &log.raw_json);
+    self.seen.insert(hash)
+  }
+
+  /// Saved time is compared at the precision of imported ones
+  fn insert_saved(&mut self, log: &Log) {
+    let time = log
+      .time
+      .duration_trunc(TimeDelta::microseconds(1))
+      .unwrap_or(log.time);
+    let hash = self.hash(log.room_id, &log.command, time, &log.raw_json);
+    self.seen.insert(hash);
+  }
+}
+
+struct Importer {
+  room_id: Option<i64>,
+  writer: LogWriter,
+  dedup: Dedup,
+  /// Time range of the records of each room, duplicated ones included
+  ranges: AHashMap<i64, (DateTime<Utc>, DateTime<Utc>)>,
+  batch: Vec<(NewLog, Option<Command>)>,
+  stats: ImportStats,
+}
+
+impl Importer {
+  async fn read_all(&mut self, files: &[PathBuf]) -> anyhow::Result<()> {
+    if files.is_empty() {
+      self.read("stdin", stdin().lock()).await?;
+    }
+    for path in files {
+      let file =
+        File::open(path).with_context(|| format!("Failed to open `{}`", path.to_string_lossy()))?;
+      self
+        .read(&path.to_string_lossy(), BufReader::new(file))
+        .await?;
+    }
+    self.flush().await
+  }
+
+  async fn read(&mut self, name: &str, reader: impl BufRead) -> anyhow::Result<()> {
+    for (idx, line) in reader.lines().enumerate() {
+      let line = line.with_context(|| format!("Failed to read `{name}`"))?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      match self.parse(&line) {
+        Ok((log, cmd)) => self.batch.push((log, cmd)),
+        Err(err) => {
+          self.stats.invalid += 1;
+          log::warn!("Skipped line {} of `{name}`: {err:#}", idx + 1);
+          continue;
+        },
+      }
+      if self.batch.len() >= IMPORT_BATCH_SIZE {
+        self.flush().await?;
+      }
+    }
+    Ok(())
+  }
+
+  fn parse(&self, line: &str) -> anyhow::Result<(NewLog, Option<Command>)> {
+    let record: ImportRecord = serde_json::from_str(line).context("Malformed record")?;
+    let Some(room_id) = record.room_id.or(self.room_id) else {
+      bail!("No room id, it can be given by --room");
+    };
+    let time = record.time.to_utc().context("Time out of range")?;
+    let Some(command) = record.raw_json.get("cmd").and_then(|cmd| cmd.as_str()) else {
+      bail!("No `cmd` in raw json");
+    };
+    let command = command.to_string();
+    let cmd = match serde_json::from_value::<MaybeCommand>(record.raw_json.clone()) {
+      Ok(MaybeCommand::Command(cmd)) => Some(cmd),
+      Ok(MaybeCommand::Unknown(_)) => None,
+      Err(err) => return Err(err).context("Invalid command"),
+    };
+    let log = NewLog {
+      room_id,
+      command,
+      related_uid: cmd.as_ref().and_then(related_uid),
+      raw_json: record.raw_json,
+      time,
+    };
+    Ok((log, cmd))
+  }
+
+  /// Looks up saved logs at the times of the batch, and queues the others.
+  async fn flush(&mut self) -> anyhow::Result<()> {
+    let batch = std::mem::take(&mut self.batch);
+    let mut rooms: AHashMap<i64, AHashSet<DateTime<Utc>>> = AHashMap::new();
+    for (log, _) in &batch {
+      rooms.entry(log.room_id).or_default().insert(log.time);
+    }
+    for (room_id, times) in rooms {
+      self.lookup(room_id, times).await?;
+    }
+
+    for (log, cmd) in batch {
+      let range = self
+        .ranges
+        .entry(log.room_id)
+        .or_insert((log.time, log.time));
+      *range = (range.0.min(log.time), range.1.max(log.time));
+      if !self.dedup.insert(&log) {
+        self.stats.duplicated += 1;
+        continue;
+      }
+      self.stats.imported += 1;
+      if cmd.is_none() {
+        self.stats.unknown += 1;
+      }
+      self.writer.write_imported(log, cmd).await?;
+    }
+    Ok(())
+  }
+
+  /// Saved logs are looked up by exact time, so the index is used however the archive is sorted
+  async fn lookup(&mut self, room_id: i64, times: AHashSet<DateTime<Utc>>) -> anyhow::Result<()> {
+    let times: Vec<DateTime<Utc>> = times.into_iter().collect();
+    let logs = global_state()
+      .store()
+      .saved_at(room_id, &times)
+      .await
+      .with_context(|| format!("Failed to look up saved logs of room {room_id}"))?;
+    for log in &logs {
+      self.dedup.insert_saved(log);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn unix_time_in_seconds_or_millis() {
+    let expected = DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z").unwrap();
+    assert_eq!(ImportTime::Unix(1704164645).to_utc(), Some(expected.into()));
+    let expected = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678Z").unwrap();
+    assert_eq!(
+      ImportTime::Unix(1704164645678).to_utc(),
+      Some(expected.into())
+    );
+    let date: ImportTime = serde_json::from_value(json!("2024-01-02T03:04:05.678901234Z")).unwrap();
+    let expected = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678901Z").unwrap();
+    assert_eq!(date.to_utc(), Some(expected.into()));
+  }
+
+  #[test]
+  fn dedup_by_content() {
+    let time = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678901Z")
+      .unwrap()
+      .into();
+    let log = NewLog {
+      room_id: 1,
+      command: "DANMU_MSG".to_string(),
+      raw_json: json!({"cmd": "DANMU_MSG", "info": [1]}),
+      time,
+      related_uid: None,
+    };
+    let mut dedup = Dedup::default();
+    assert!(dedup.insert(&log));
+    assert!(!dedup.insert(&log.clone()));
+    let other = NewLog {
+      raw_json: json!({"cmd": "DANMU_MSG", "info": [2]}),
+      ..log.clone()
+    };
+    assert!(dedup.insert(&other));
+
+    let mut dedup = Dedup::default();
+    dedup.insert_saved(&Log {
+      id: 1,
+      room_id: log.room_id,
+      command: log.command.clone(),
+      raw_json: log.raw_json.clone(),
+      time: time + TimeDelta::nanoseconds(234),
+      related_uid: None,
+    });
+    assert!(!dedup.insert(&log));
+  }
+}
@@ -0,0 +1,115 @@
+use ahash::AHashMap;
+
+use crate::config::{CommandFilter, IngestConfig};
+
+impl CommandFilter {
+  fn allows(&self, command: &str) -> bool {
+    (self.include.is_empty() || self.include.iter().any(|cmd| cmd == command))
+      && !self.exclude.iter().any(|cmd| cmd == command)
+  }
+}
+
+/// Decides which commands of a room are saved, created per collector
+/// as sampling counts the logs of each command.
+#[derive(Debug)]
+pub struct IngestFilter {
+  global: CommandFilter,
+  room: Option<CommandFilter>,
+  /// Logs seen of sampled commands since the last one saved
+  counters: AHashMap<String, u32>,
+}
+
+impl IngestFilter {
+  pub fn new(config: &IngestConfig, room_id: u64) -> IngestFilter {
+    IngestFilter {
+      global: config.global.clone(),
+      room: config.rooms.get(&room_id.to_string()).cloned(),
+      counters: AHashMap::new(),
+    }
+  }
+
+  /// Whether the log of the command should be saved, both global and room rules must allow it.
+  /// The sampling rate of the room takes precedence over the global one.
+  pub fn accepts(&mut self, command: &str) -> bool {
+    if !self.global.allows(command) || self.room.as_ref().is_some_and(|room| !room.allows(command))
+    {
+      return false;
+    }
+    let every = self
+      .room
+      .as_ref()
+      .and_then(|room| room.sample.get(command))
+      .or_else(|| self.global.sample.get(command))
+      .copied()
+      .unwrap_or(1);
+    if every <= 1 {
+      return true;
+    }
+    let counter = self.counters.entry(command.to_string()).or_default();
+    let accepted = *counter == 0;
+    *counter = (*counter + 1) % every;
+    accepted
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filter(config: &str, room_id: u64) -> IngestFilter {
+    let config: IngestConfig = toml::from_str(config).unwrap();
+    IngestFilter::new(&config, room_id)
+  }
+
+  fn accepted(filter: &mut IngestFilter, command: &str, times: usize) -> usize {
+    (0..times).filter(|_| filter.accepts(command)).count()
+  }
+
+  #[test]
+  fn room_sample_overrides_global() {
+    let config = r#"
+      sample = { LIKE_INFO_V3_UPDATE = 10, ONLINE_RANK_COUNT = 5 }
+      [rooms.1]
+      sample = { LIKE_INFO_V3_UPDATE = 2 }
+    "#;
+    let mut room = filter(config, 1);
+    assert_eq!(accepted(&mut room, "LIKE_INFO_V3_UPDATE", 20), 10);
+    assert_eq!(accepted(&mut room, "ONLINE_RANK_COUNT", 20), 4);
+    assert_eq!(accepted(&mut room, "DANMU_MSG", 20), 20);
+
+    let mut other = filter(config, 2);
+    assert_eq!(accepted(&mut other, "LIKE_INFO_V3_UPDATE", 20), 2);
+  }
+
+  #[test]
+  fn include_and_exclude() {
+    let config = r#"
+      include = ["DANMU_MSG", "SEND_GIFT", "SUPER_CHAT_MESSAGE"]
+      exclude = ["SEND_GIFT"]
+      [rooms.1]
+      exclude = ["SUPER_CHAT_MESSAGE"]
+    "#;
+    let mut other = filter(config, 2);
+    assert!(other.accepts("DANMU_MSG"));
+    assert!(!other.accepts("SEND_GIFT"));
+    assert!(other.accepts("SUPER_CHAT_MESSAGE"));
+    assert!(!other.accepts("LIKE_INFO_V3_UPDATE"));
+
+    // Both global and room rules apply
+    let mut room = filter(config, 1);
+    assert!(room.accepts("DANMU_MSG"));
+    assert!(!room.accepts("SEND_GIFT"));
+    assert!(!room.accepts("SUPER_CHAT_MESSAGE"));
+    assert!(!room.accepts("LIKE_INFO_V3_UPDATE"));
+  }
+
+  #[test]
+  fn sample_of_zero_or_one_saves_all() {
+    let mut filter = filter(
+      "sample = { LIKE_INFO_V3_UPDATE = 0, ONLINE_RANK_COUNT = 1 }",
+      1,
+    );
+    assert_eq!(accepted(&mut filter, "LIKE_INFO_V3_UPDATE", 5), 5);
+    assert_eq!(accepted(&mut filter, "ONLINE_RANK_COUNT", 5), 5);
+  }
+}
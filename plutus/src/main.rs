@@ -1,11 +1,11 @@
 use std::{
+  fmt::Display,
   fs,
   hash::BuildHasherDefault,
   io::{stdin, BufRead, BufReader, Read},
   num::NonZeroU64,
   process::exit,
   str::FromStr,
-  sync::Arc,
   time::Duration,
 };
 
@@ -13,13 +13,15 @@ use anyhow::{anyhow, bail, Context};
 use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use clap::{Parser, Subcommand};
 use dashmap::DashMap;
-use diesel_async::RunQueryDsl;
 use futures_util::StreamExt;
 use plutus_core::{
-  api::live::MessageConnection,
+  api::live::{Backoff, MessageConnection, ReconnectEvent},
   client::Client,
   data::{
-    live::cmds::{Command, GuardLevel, MaybeCommand},
+    live::{
+      cmds::{Command, GuardLevel, MaybeCommand},
+      MedalInfo,
+    },
     passport::{QrLoginData, QrLoginStatus},
   },
 };
@@ -30,22 +32,47 @@ use tokio::join;
 use crate::{
   data::passport::QrLoginQuery,
   error::AnyhowExt,
+  export::ExportCommand,
+  feed::{FeedFilter, Filter},
+  import::ImportCommand,
+  ingest::IngestFilter,
+  metrics::Metrics,
   models::{Log, NewLog},
+  reindex::ReindexCommand,
   resp::{Cursor, Paginated, Resp},
+  rooms::{RoomRegistry, RoomStatus, SharedRoomStatus},
   routes::{server, QueryBody, TimeRange},
-  state::{AsyncPoolConnection, State},
+  state::State,
+  stats::GOLD_PER_CNY,
+  writer::LogWriter,
 };
 use plutus_core::*;
 
 mod config;
 mod error;
+mod export;
+mod feed;
+mod import;
+mod ingest;
+mod metrics;
 mod models;
+mod reindex;
 mod resp;
+mod retention;
+mod rooms;
 mod routes;
+mod sessions;
+mod spool;
 mod state;
+mod stats;
+mod store;
+mod typed;
+mod writer;
 
 #[rustfmt::skip]
 mod schema;
+#[rustfmt::skip]
+mod schema_sqlite;
 
 pub type ADashMap<K, V> = DashMap<K, V, BuildHasherDefault<ahash::AHasher>>;
 
@@ -61,6 +88,14 @@ enum Action {
   Server,
   /// View saved comments
   Query(QueryCommand),
+  /// View live comments continuously
+  Tail(TailCommand),
+  /// Exports saved logs as NDJSON, CSV or Parquet
+  Export(ExportCommand),
+  /// Imports archived logs from NDJSON
+  Import(ImportCommand),
+  /// Parses saved logs again to fill `related_uid` and typed tables
+  Reindex(ReindexCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -75,6 +110,17 @@ struct QueryCommand {
   #[clap(short, long, value_delimiter = ' ', num_args = 1..)]
   pub commands: Vec<String>,
 
+  /// Only view results during a live session, see `/rooms/{id}/sessions`
+  #[clap(long)]
+  pub session: Option<i64>,
+
+  /// Only danmaku and SuperChat containing the keyword, case-insensitive
+  #[clap(short, long)]
+  pub keyword: Option<String>,
+  /// Only danmaku and SuperChat matching the POSIX regular expression
+  #[clap(long)]
+  pub regex: Option<String>,
+
   /// Prints raw JSON
   #[clap(long)]
   pub raw: bool,
@@ -90,11 +136,42 @@ struct QueryCommand {
   pub page: NonZeroU64,
   #[clap(long, default_value = "500")]
   pub size: NonZeroU64,
+  /// Continues after the cursor printed at the end of the previous page, `--page` is ignored
+  #[clap(long)]
+  pub after: Option<String>,
+  /// Skips counting total pages, which is slow for large rooms
+  #[clap(long)]
+  pub no_count: bool,
 
   #[clap(short, long, default_value = "http://127.0.0.1:7727")]
   pub server: String,
 }
 
+#[derive(Parser, Debug)]
+struct TailCommand {
+  /// Room id, a short room id also works when connecting to Bilibili directly
+  #[arg(short, long)]
+  pub room: u64,
+  /// Filter specific UID
+  #[arg(short, long)]
+  pub uid: Option<u64>,
+  /// Commands, e.g. "DANMU_MSG", "SUPER_CHAT_MESSAGE", "GUARD_BUY"
+  #[clap(short, long, value_delimiter = ' ', num_args = 1..)]
+  pub commands: Vec<String>,
+
+  /// Prints raw JSON
+  #[clap(long)]
+  pub raw: bool,
+
+  /// Connects to Bilibili as a guest without logging in, usernames are masked
+  #[clap(long, conflicts_with = "server")]
+  pub guest: bool,
+
+  /// Subscribes to the live feed of a running server instead of connecting to Bilibili directly
+  #[clap(short, long)]
+  pub server: Option<String>,
+}
+
 /// assume input is local date time, and convert it to UTC
 fn parse_date(arg: &str) -> anyhow::Result<DateTime<Utc>> {
   let local_tz = *Local::now().offset();
@@ -123,11 +200,25 @@ pub fn global_state() -> &'static State {
   unsafe { GLOBAL_STATE.as_ref().unwrap() }
 }
 
-pub static mut STATS_MAP: Option<Arc<ADashMap<String, u64>>> = None;
+pub static mut METRICS: Option<Metrics> = None;
+
+pub fn metrics() -> &'static Metrics {
+  #[allow(static_mut_refs)]
+  unsafe { METRICS.as_ref().unwrap() }
+}
+
+pub static mut ROOM_REGISTRY: Option<RoomRegistry> = None;
+
+pub fn room_registry() -> &'static RoomRegistry {
+  #[allow(static_mut_refs)]
+  unsafe { ROOM_REGISTRY.as_ref().unwrap() }
+}
+
+pub static mut LOG_WRITER: Option<LogWriter> = None;
 
-pub fn stats_map() -> Arc<ADashMap<String, u64>> {
+pub fn log_writer() -> &'static LogWriter {
   #[allow(static_mut_refs)]
-  unsafe { STATS_MAP.clone().unwrap() }
+  unsafe { LOG_WRITER.as_ref().unwrap() }
 }
 
 pub const PLUTUS_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -144,6 +235,18 @@ async fn async_main() -> anyhow::Result<()> {
     Action::Query(action) => {
       query(action).await?;
     },
+    Action::Tail(action) => {
+      tail(action).await?;
+    },
+    Action::Export(action) => {
+      export::export_to(action).await?;
+    },
+    Action::Import(action) => {
+      import::import(action).await?;
+    },
+    Action::Reindex(action) => {
+      reindex::reindex(action).await?;
+    },
   }
   Ok(())
 }
@@ -155,21 +258,31 @@ async fn main_server() -> anyhow::Result<()> {
 
   unsafe {
     GLOBAL_STATE = Some(state.clone());
-    STATS_MAP = Some(Arc::new(ADashMap::default()));
+    METRICS = Some(Metrics::new().context("Failed to init metrics")?);
+    LOG_WRITER = Some(LogWriter::start(&state.config.writer).context("Failed to start writer")?);
   }
 
   let client = Client::new()?;
-  login_if_not(&client).await?;
+  if state.config.guest {
+    log::warn!("Collecting as a guest, usernames in the logs are masked");
+  } else {
+    login_if_not(&client).await?;
+  }
+
+  unsafe {
+    ROOM_REGISTRY = Some(RoomRegistry::new(client));
+  }
+
+  tokio::spawn(retention::run(state.config.retention.clone()));
 
   let address = state.config.address;
-  let (collector, stats_printer, server) = join!(
+  let (collector, server) = join!(
     tokio::spawn(async move {
-      collector(&client, &state.config.rooms)
+      rooms::start_all(&state.config.rooms)
         .await
         .context("collector error")
         .log()
     }),
-    tokio::spawn(async move { stats_printer().await }),
     tokio::spawn(async move {
       if server(&address).await.also_log().is_err() {
         exit(1);
@@ -178,7 +291,6 @@ async fn main_server() -> anyhow::Result<()> {
   );
   server?;
   collector?;
-  stats_printer?;
 
   Ok(())
 }
@@ -236,109 +348,139 @@ async fn login_if_not(client: &Client) -> anyhow::Result<()> {
   Ok(())
 }
 
-async fn collector(client: &Client, rooms: &[u64]) -> anyhow::Result<()> {
-  for room_id in rooms.iter() {
-    let room_id = *room_id;
-    let client = client.clone();
-    tokio::spawn(async move {
-      loop {
-        log::info!("Connecting to {room_id}");
-        let con =
-          match MessageConnection::<serde_json::Value>::connect_with_client(&client, room_id).await
-          {
-            Ok(con) => con,
-            Err(err) => {
-              log::error!("connect to {room_id} failed, sleep 10s before retrying: {err:?}");
-              tokio::time::sleep(Duration::from_secs(10)).await;
-              continue;
-            },
-          };
-        while let Some(raw_json) = { con.write().await.next().await } {
-          tokio::spawn(async move {
-            let Some(cmd_id) = raw_json.get("cmd").and_then(|cmd| cmd.as_str()) else {
-              log::warn!(
-                "Unknown command, room_id={room_id}, raw_json={}",
-                serde_json::to_string(&raw_json)
-                  .unwrap_or_else(|err| format!("Failed to deser {err:?}"))
-              );
-              return;
-            };
-
-            let mut related_uid: Option<i64> = None;
-
-            let cmd = serde_json::from_value::<MaybeCommand>(raw_json.clone());
-            if let Ok(MaybeCommand::Command(cmd)) = cmd {
-              related_uid = match cmd {
-                Command::Danmaku { data } => data.data().ok().map(|data| data.user.uid as i64),
-                Command::SuperChatMessage { data } => Some(data.uid as i64),
-                Command::GuardBuy { data } => Some(data.uid as i64),
-                Command::InteractWord { data } => Some(data.uid as i64),
-                Command::EntryEffect { data } => Some(data.uid as i64),
-                Command::LikeInfoV3Click { data } => Some(data.uid as i64),
-                _ => None,
-              };
-            };
-
-            let mut conn: AsyncPoolConnection = match global_state().db_con().await {
-              Ok(ok) => ok,
-              Err(err) => {
-                log::error!("Failed get db conn: {err:?}");
-                return;
-              },
-            };
-
-            let new_log = NewLog {
-              room_id: room_id as i64,
-              command: cmd_id.to_string(),
-              raw_json,
-              related_uid,
-              time: chrono::Utc::now(),
-            };
-            let result = diesel::insert_into(crate::schema::logs::table)
-              .values(&new_log)
-              .execute(&mut conn)
-              .await;
-            if let Err(err) = result {
-              log::error!("Failed to insert, {new_log:?}, err: {err:?}")
-            } else {
-              let map = stats_map();
-              let mut count = map.entry(new_log.command).or_insert(0);
-              *count.value_mut() += 1;
-            }
-          });
+/// Collects commands of a room until aborted by [`rooms::RoomRegistry::stop`]
+pub async fn collect_room(client: Client, room_id: u64, status: SharedRoomStatus) {
+  let room = room_id.to_string();
+  let mut filter = IngestFilter::new(&global_state().config.ingest, room_id);
+  log::info!("Connecting to {room_id}");
+  let con = MessageConnection::<serde_json::Value>::builder(&client)
+    .room(room_id)
+    .guest(global_state().config.guest)
+    .reconnecting(Backoff::default());
+  let mut con = match con {
+    Ok(con) => con,
+    Err(err) => {
+      log::error!("Failed to connect to {room_id}: {err:?}");
+      return;
+    },
+  };
+  let mut bytes_counted = 0;
+  loop {
+    // Wakes up periodically to refresh the heartbeat status of quiet rooms
+    let next = tokio::time::timeout(Duration::from_secs(30), con.next()).await;
+    if let Some(heartbeat) = con.last_heartbeat() {
+      metrics()
+        .popularity
+        .with_label_values(&[&room])
+        .set(heartbeat.popular as i64);
+      rooms::update_status(&status, |status| status.heartbeat(heartbeat));
+    }
+    let bytes_received = con.bytes_received();
+    metrics()
+      .received_bytes
+      .with_label_values(&[&room])
+      .inc_by(bytes_received - bytes_counted);
+    bytes_counted = bytes_received;
+    metrics()
+      .channel_backlog
+      .with_label_values(&[&room])
+      .set(con.backlog() as i64);
+
+    let raw_json = match next {
+      Ok(Some(ReconnectEvent::Command(raw_json))) => raw_json,
+      Ok(Some(ReconnectEvent::Connected { host, gap })) => {
+        match gap {
+          Some(gap) => log::info!("Reconnected to {room_id} via {host}, no data for {gap:?}"),
+          None => log::info!("Connected to {room_id} via {host}"),
         }
-        log::error!("Room {room_id} conn closed, sleep 10s before reconnecting");
-        tokio::time::sleep(Duration::from_secs(10)).await;
-      }
-    });
+        rooms::update_status(&status, |status| {
+          status.connecting();
+          status.connected();
+        });
+        sessions::sync_room(&client, room_id)
+          .await
+          .with_context(|| format!("Failed to sync session of room {room_id}"))
+          .log();
+        continue;
+      },
+      Ok(Some(ReconnectEvent::Disconnected {
+        error,
+        attempt,
+        retry_in,
+      })) => {
+        log::error!(
+          "Room {room_id} disconnected ({attempt} in a row), retry in {retry_in:?}: {error}"
+        );
+        metrics().reconnects.with_label_values(&[&room]).inc();
+        rooms::update_status(&status, |status| status.disconnected(error));
+        continue;
+      },
+      Ok(Some(ReconnectEvent::AuthFailed(code))) => {
+        log::error!("Room {room_id} rejected the certificate with code {code}");
+        continue;
+      },
+      // Taken from `last_heartbeat` above
+      Ok(Some(ReconnectEvent::Popularity(_))) => continue,
+      Ok(Some(ReconnectEvent::DecodeError(err))) => {
+        log::warn!("Room {room_id} sent a packet that failed to decode: {err}");
+        continue;
+      },
+      Ok(None) => break,
+      Err(_elapsed) => continue,
+    };
+    rooms::update_status(&status, RoomStatus::received);
+
+    let Some(cmd_id) = raw_json.get("cmd").and_then(|cmd| cmd.as_str()) else {
+      log::warn!(
+        "Unknown command, room_id={room_id}, raw_json={}",
+        serde_json::to_string(&raw_json).unwrap_or_else(|err| format!("Failed to deser {err:?}"))
+      );
+      continue;
+    };
+
+    let cmd = match serde_json::from_value::<MaybeCommand>(raw_json.clone()) {
+      Ok(MaybeCommand::Command(cmd)) => Some(cmd),
+      _ => None,
+    };
+    let related_uid: Option<i64> = cmd.as_ref().and_then(related_uid);
+
+    let new_log = NewLog {
+      room_id: room_id as i64,
+      command: cmd_id.to_string(),
+      raw_json,
+      related_uid,
+      time: chrono::Utc::now(),
+    };
+    global_state().publish(&new_log);
+    metrics()
+      .commands
+      .with_label_values(&[&room, &new_log.command])
+      .inc();
+    // Sessions are tracked by their commands, so those are always saved
+    if !cmd.as_ref().is_some_and(sessions::is_session_command) && !filter.accepts(&new_log.command)
+    {
+      metrics()
+        .filtered_commands
+        .with_label_values(&[&room, &new_log.command])
+        .inc();
+      continue;
+    }
+    log_writer().write(new_log, cmd).await;
   }
-  Ok(())
+  log::error!("Room {room_id} connection stopped");
 }
 
-async fn stats_printer() {
-  let dur = Duration::from_secs(60);
-  tokio::time::sleep(dur).await;
-  let mut timer = tokio::time::interval(dur);
-  loop {
-    timer.tick().await;
-    let mut maps = {
-      let map = stats_map();
-      let maps: Vec<_> = map.iter().map(|r| (r.key().clone(), *r.value())).collect();
-      map.clear();
-      maps
-    };
-    // descending
-    maps.sort_unstable_by(|(_k1, v1), (_k2, v2)| v2.cmp(v1));
-    let total: u64 = maps.iter().map(|(_k, v)| *v).sum();
-    let top5 = maps
-      .into_iter()
-      .take(5)
-      .map(|(k, v)| format!("{k}={v}"))
-      .collect::<Vec<_>>()
-      .join(", ");
-    log::info!(
-      "A total of {total} commands were collected in the past minute, and the top five are: {top5}",
-    );
+fn related_uid(cmd: &Command) -> Option<i64> {
+  match cmd {
+    Command::Danmaku { data } => data.data().ok().map(|data| data.user.uid as i64),
+    Command::SuperChatMessage { data } => Some(data.uid as i64),
+    Command::GuardBuy { data } => Some(data.uid as i64),
+    Command::InteractWord { data } => Some(data.uid as i64),
+    Command::EntryEffect { data } => Some(data.uid as i64),
+    Command::LikeInfoV3Click { data } => Some(data.uid as i64),
+    Command::SendGift { data } => Some(data.uid as i64),
+    Command::ComboSend { data } => Some(data.uid as i64),
+    _ => None,
   }
 }
 
@@ -360,12 +502,17 @@ fn guess_addr_from_config() -> Option<String> {
   config.address
 }
 
-async fn query(query: QueryCommand) -> anyhow::Result<()> {
-  let mut host = guess_addr_from_config().unwrap_or(query.server);
-  let client = reqwest::Client::new();
+fn with_http_schema(host: String) -> String {
   if !host.starts_with("https://") && !host.starts_with("http://") {
-    host = format!("http://{host}");
+    format!("http://{host}")
+  } else {
+    host
   }
+}
+
+async fn query(query: QueryCommand) -> anyhow::Result<()> {
+  let host = with_http_schema(guess_addr_from_config().unwrap_or(query.server));
+  let client = reqwest::Client::new();
 
   let resp = client
     .post(format!("{host}/list"))
@@ -377,9 +524,14 @@ async fn query(query: QueryCommand) -> anyhow::Result<()> {
         start: query.start,
         end: query.end,
       }),
+      session: query.session,
+      count: !query.no_count,
+      keyword: query.keyword,
+      regex: query.regex,
       cursor: Cursor {
         page: query.page,
         size: query.size,
+        after: query.after,
       },
     })
     .send()
@@ -430,64 +582,9 @@ async fn query(query: QueryCommand) -> anyhow::Result<()> {
       continue;
     };
     let ts = log.time.with_timezone(&local_tz).format("%m-%d %H:%M");
-    match command {
-      Command::CutOff { data } => {
-        markdown.push_str(&format!("[{ts}]直播被切断，原因为: {}\n", data.message));
-      },
-      Command::Danmaku { data } => {
-        let data = data.data().context("Unable to parse danmaku info")?;
-        let medal = data
-          .medal
-          .as_ref()
-          .map(|medal| {
-            let name = &medal.name;
-            let level = medal.level;
-            let guard_level = format_guard_level(medal.guard_level);
-            format!("[{name}-{level}]{guard_level}")
-          })
-          .unwrap_or_else(String::new);
-
-        let link = format!("https://space.bilibili.com/{}/", data.user.uid);
-        let user = Link::new(&data.user.username, &link);
-        markdown.push_str(&format!("[{ts}]{user}{medal}: {}\n", data.content));
-      },
-      Command::GuardBuy { data } => {
-        let link = format!("https://space.bilibili.com/{}/", data.uid);
-        let user = Link::new(&data.username, &link);
-        let level = format_guard_level(data.guard_level);
-        markdown.push_str(&format!(
-          "[{ts}]{user}购买了{level}，花费{price}\n",
-          price = data.price
-        ));
-      },
-      Command::Living { data: _ } => {
-        markdown.push_str("[{ts}]开播\n");
-      },
-      Command::Preparing { data: _ } => {
-        markdown.push_str("[{ts}]下播\n");
-      },
-      Command::RoomSilentOff => {
-        markdown.push_str("[{ts}]禁言关闭\n");
-      },
-      Command::RoomSilentOn { data } => {
-        markdown.push_str(&format!("[{ts}]禁言开启:{data:?}\n"));
-      },
-      Command::SuperChatMessage { data } => {
-        let link = format!("https://space.bilibili.com/{}/", data.uid);
-        let user = Link::new(&data.user.username, &link);
-        let price = data.price;
-
-        markdown.push_str(&format!(
-          "[{ts}][SuperChat][{price}]{user}: {}\n",
-          data.message.unwrap_or_default()
-        ));
-      },
-      Command::Warning { data } => {
-        markdown.push_str(&format!("[{ts}]超管警告: {}\n", data.message));
-      },
-      _ => {
-        ignored += 1;
-      },
+    match render_command(ts, command)? {
+      Some(line) => markdown.push_str(&line),
+      None => ignored += 1,
     }
   }
 
@@ -499,10 +596,260 @@ async fn query(query: QueryCommand) -> anyhow::Result<()> {
   }
 
   termimad::print_inline(&markdown);
+  if let Some(next) = data.next {
+    println!("--- 下一页: --after {next} ---");
+  }
+
+  Ok(())
+}
+
+async fn tail(tail: TailCommand) -> anyhow::Result<()> {
+  let feed_filter = FeedFilter {
+    room_id: Some(tail.room),
+    commands: (!tail.commands.is_empty()).then(|| tail.commands.join(",")),
+    uid: tail.uid,
+  };
+  match tail.server {
+    Some(server) => tail_server(&with_http_schema(server), &feed_filter, tail.raw).await,
+    None => {
+      // The connection only receives commands of the room, and `tail.room` may be a short id
+      let filter = FeedFilter {
+        room_id: None,
+        ..feed_filter
+      };
+      tail_direct(tail.room, filter.into(), tail.raw, tail.guest).await
+    },
+  }
+}
+
+/// Receives commands from Bilibili, filters them locally
+async fn tail_direct(room_id: u64, filter: Filter, raw: bool, guest: bool) -> anyhow::Result<()> {
+  let client = Client::new()?;
+  log::info!("Connecting to {room_id}");
+  let mut con = MessageConnection::<serde_json::Value>::builder(&client)
+    .room(room_id)
+    .guest(guest)
+    .reconnecting(Backoff::default())?;
+  while let Some(event) = con.next().await {
+    let raw_json = match event {
+      ReconnectEvent::Command(raw_json) => raw_json,
+      ReconnectEvent::Connected { host, gap } => {
+        if let Some(gap) = gap {
+          log::warn!("Reconnected to {room_id} via {host}, no data for {gap:?}");
+        }
+        continue;
+      },
+      ReconnectEvent::Disconnected {
+        error, retry_in, ..
+      } => {
+        log::error!("Room {room_id} disconnected, retry in {retry_in:?}: {error}");
+        continue;
+      },
+      ReconnectEvent::AuthFailed(code) => {
+        log::error!("Room {room_id} rejected the certificate with code {code}");
+        continue;
+      },
+      ReconnectEvent::Popularity(_) => continue,
+      ReconnectEvent::DecodeError(err) => {
+        log::warn!("Room {room_id} sent a packet that failed to decode: {err}");
+        continue;
+      },
+    };
+    let Some(cmd_id) = raw_json.get("cmd").and_then(|cmd| cmd.as_str()) else {
+      continue;
+    };
+    let related_uid = serde_json::from_value::<Command>(raw_json.clone())
+      .ok()
+      .as_ref()
+      .and_then(related_uid);
+    let log = NewLog {
+      room_id: room_id as i64,
+      command: cmd_id.to_string(),
+      raw_json,
+      related_uid,
+      time: Utc::now(),
+    };
+    if filter.matches(&log) {
+      print_tail(log, raw);
+    }
+  }
+  Ok(())
+}
+
+/// Receives collected commands from the server's `/feed/sse`, filtered by the server
+async fn tail_server(host: &str, filter: &FeedFilter, raw: bool) -> anyhow::Result<()> {
+  let mut resp = reqwest::Client::new()
+    .get(format!("{host}/feed/sse"))
+    .query(filter)
+    .send()
+    .await
+    .context("Failed to subscribe live feed")?;
+  if !resp.status().is_success() {
+    let status = resp.status();
+    let text = resp.text().await.context("Failed to parse body as text")?;
+    return Err(anyhow!("Failed to subscribe live feed: {status}, {text}"));
+  }
 
+  let mut buf: Vec<u8> = Vec::with_capacity(4096);
+  while let Some(chunk) = resp.chunk().await.context("Failed to read live feed")? {
+    buf.extend_from_slice(&chunk);
+    // Server-Sent Events are separated by a blank line
+    while let Some(idx) = buf.windows(2).position(|window| window == b"\n\n") {
+      let event: Vec<u8> = buf.drain(..idx + 2).collect();
+      let event = String::from_utf8_lossy(&event);
+      let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect::<Vec<_>>()
+        .join("\n");
+      if data.is_empty() {
+        continue;
+      }
+      match serde_json::from_str::<NewLog>(&data) {
+        Ok(log) => print_tail(log, raw),
+        Err(err) => log::warn!("Skipped malformed event of live feed: {err}"),
+      }
+    }
+  }
+  log::warn!("Live feed closed by server");
   Ok(())
 }
 
+/// Commands failed to render are logged and skipped, so tailing goes on
+fn print_tail(log: NewLog, raw: bool) {
+  if raw {
+    match serde_json::to_string(&log.raw_json) {
+      Ok(json) => println!("{json}"),
+      Err(err) => println!("{err}: {}", log.raw_json),
+    }
+    return;
+  }
+  let Some(command) = serde_json::from_value::<Command>(log.raw_json).ok() else {
+    return;
+  };
+  let ts = log.time.with_timezone(&Local).format("%H:%M:%S");
+  match render_command(ts, command) {
+    Ok(Some(line)) => termimad::print_inline(&line),
+    Ok(None) => {},
+    Err(err) => log::warn!("Skipped {}: {err:#}", log.command),
+  }
+}
+
+/// Renders a command into a markdown line, `None` if the command is not supported
+fn render_command(ts: impl Display, command: Command) -> anyhow::Result<Option<String>> {
+  let line = match command {
+    Command::CutOff { data } => {
+      format!("[{ts}]直播被切断，原因为: {}\n", data.message)
+    },
+    Command::Danmaku { data } => {
+      let data = data.data().context("Unable to parse danmaku info")?;
+      let medal = data
+        .medal
+        .as_ref()
+        .map(|medal| {
+          let name = &medal.name;
+          let level = medal.level;
+          let guard_level = format_guard_level(medal.guard_level);
+          format!("[{name}-{level}]{guard_level}")
+        })
+        .unwrap_or_else(String::new);
+
+      let link = format!("https://space.bilibili.com/{}/", data.user.uid);
+      let user = Link::new(&data.user.username, &link);
+      format!("[{ts}]{user}{medal}: {}\n", data.content)
+    },
+    Command::GuardBuy { data } => {
+      let link = format!("https://space.bilibili.com/{}/", data.uid);
+      let user = Link::new(&data.username, &link);
+      let level = format_guard_level(data.guard_level);
+      format!(
+        "[{ts}]{user}购买了{level}，花费{price}\n",
+        price = data.price
+      )
+    },
+    Command::SendGift { data } => {
+      let link = format!("https://space.bilibili.com/{}/", data.uid);
+      let user = Link::new(&data.username, &link);
+      let medal = data
+        .medal
+        .as_ref()
+        .map(format_medal_info)
+        .unwrap_or_default();
+      let value = if data.is_paid() {
+        format!("，价值{}元", format_gold_coin(data.total_coin))
+      } else {
+        String::new()
+      };
+      format!(
+        "[{ts}]{user}{medal}{action}了{num}个{gift}{value}\n",
+        action = data.action,
+        num = data.num,
+        gift = data.gift_name,
+      )
+    },
+    Command::ComboSend { data } => {
+      let link = format!("https://space.bilibili.com/{}/", data.uid);
+      let user = Link::new(&data.username, &link);
+      let medal = data
+        .medal
+        .as_ref()
+        .map(format_medal_info)
+        .unwrap_or_default();
+      format!(
+        "[{ts}]{user}{medal}{action}{gift}共{num}个，连击x{combo}，价值{value}元\n",
+        action = data.action,
+        gift = data.gift_name,
+        num = data.total_num,
+        combo = data.combo_num,
+        value = format_gold_coin(data.combo_total_coin),
+      )
+    },
+    Command::Living { data: _ } => {
+      format!("[{ts}]开播\n")
+    },
+    Command::Preparing { data: _ } => {
+      format!("[{ts}]下播\n")
+    },
+    Command::RoomSilentOff => {
+      format!("[{ts}]禁言关闭\n")
+    },
+    Command::RoomSilentOn { data } => {
+      format!("[{ts}]禁言开启:{data:?}\n")
+    },
+    Command::SuperChatMessage { data } => {
+      let link = format!("https://space.bilibili.com/{}/", data.uid);
+      let user = Link::new(&data.user.username, &link);
+      let price = data.price;
+
+      format!(
+        "[{ts}][SuperChat][{price}]{user}: {}\n",
+        data.message.unwrap_or_default()
+      )
+    },
+    Command::Warning { data } => {
+      format!("[{ts}]超管警告: {}\n", data.message)
+    },
+    _ => return Ok(None),
+  };
+  Ok(Some(line))
+}
+
+fn format_medal_info(medal: &MedalInfo) -> String {
+  if medal.medal_level == 0 {
+    return String::new();
+  }
+  let name = &medal.medal_name;
+  let level = medal.medal_level;
+  let guard_level = format_guard_level(medal.guard_level);
+  format!("[{name}-{level}]{guard_level}")
+}
+
+/// 1000 gold coins = 1 CNY
+fn format_gold_coin(coin: u64) -> String {
+  format!("{:.1}", coin as f64 / GOLD_PER_CNY as f64)
+}
+
 fn format_guard_level(level: GuardLevel) -> &'static str {
   match level {
     data::live::cmds::GuardLevel::None => "",
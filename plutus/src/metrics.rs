@@ -0,0 +1,135 @@
+use axum::{
+  http::{header, StatusCode},
+  response::{IntoResponse, Response},
+};
+use prometheus::{
+  Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+  TextEncoder,
+};
+
+use crate::metrics;
+
+/// Prometheus metrics of the collectors, scraped from `/metrics`
+pub struct Metrics {
+  registry: Registry,
+  pub commands: IntCounterVec,
+  pub filtered_commands: IntCounterVec,
+  pub db_insert_seconds: Histogram,
+  pub db_insert_failures: IntCounterVec,
+  pub dropped_rows: IntCounterVec,
+  pub spooled_rows: IntCounterVec,
+  pub writer_queue: IntGauge,
+  pub reconnects: IntCounterVec,
+  pub channel_backlog: IntGaugeVec,
+  pub received_bytes: IntCounterVec,
+  pub popularity: IntGaugeVec,
+}
+
+impl Metrics {
+  pub fn new() -> anyhow::Result<Metrics> {
+    let registry = Registry::new_custom(Some("plutus".to_string()), None)?;
+    let commands = IntCounterVec::new(
+      Opts::new("commands_total", "Commands received"),
+      &["room", "command"],
+    )?;
+    let filtered_commands = IntCounterVec::new(
+      Opts::new(
+        "filtered_commands_total",
+        "Commands not saved by the ingest rules",
+      ),
+      &["room", "command"],
+    )?;
+    let db_insert_seconds = Histogram::with_opts(HistogramOpts::new(
+      "db_insert_seconds",
+      "Latency of inserting a batch of logs",
+    ))?;
+    let db_insert_failures = IntCounterVec::new(
+      Opts::new("db_insert_failures_total", "Logs failed to insert"),
+      &["room"],
+    )?;
+    let dropped_rows = IntCounterVec::new(
+      Opts::new(
+        "dropped_rows_total",
+        "Logs dropped because the writer queue is full",
+      ),
+      &["room"],
+    )?;
+    let spooled_rows = IntCounterVec::new(
+      Opts::new(
+        "spooled_rows_total",
+        "Logs saved to the spool while the database is unavailable",
+      ),
+      &["room"],
+    )?;
+    let writer_queue = IntGauge::new("writer_queue_length", "Logs waiting to be inserted")?;
+    let reconnects = IntCounterVec::new(
+      Opts::new("reconnects_total", "Reconnections of the room"),
+      &["room"],
+    )?;
+    let channel_backlog = IntGaugeVec::new(
+      Opts::new(
+        "channel_backlog",
+        "Commands received by the connection but not consumed yet",
+      ),
+      &["room"],
+    )?;
+    let received_bytes = IntCounterVec::new(
+      Opts::new("websocket_received_bytes_total", "WebSocket bytes received"),
+      &["room"],
+    )?;
+    let popularity = IntGaugeVec::new(
+      Opts::new(
+        "room_popularity",
+        "Popularity in the last heartbeat response",
+      ),
+      &["room"],
+    )?;
+
+    registry.register(Box::new(commands.clone()))?;
+    registry.register(Box::new(filtered_commands.clone()))?;
+    registry.register(Box::new(db_insert_seconds.clone()))?;
+    registry.register(Box::new(db_insert_failures.clone()))?;
+    registry.register(Box::new(dropped_rows.clone()))?;
+    registry.register(Box::new(spooled_rows.clone()))?;
+    registry.register(Box::new(writer_queue.clone()))?;
+    registry.register(Box::new(reconnects.clone()))?;
+    registry.register(Box::new(channel_backlog.clone()))?;
+    registry.register(Box::new(received_bytes.clone()))?;
+    registry.register(Box::new(popularity.clone()))?;
+
+    Ok(Metrics {
+      registry,
+      commands,
+      filtered_commands,
+      db_insert_seconds,
+      db_insert_failures,
+      dropped_rows,
+      spooled_rows,
+      writer_queue,
+      reconnects,
+      channel_backlog,
+      received_bytes,
+      popularity,
+    })
+  }
+
+  /// Drops gauges of the room after it is removed, so they don't stay at stale values
+  pub fn remove_room(&self, room_id: u64) {
+    let room = room_id.to_string();
+    let _ = self.channel_backlog.remove_label_values(&[&room]);
+    let _ = self.popularity.remove_label_values(&[&room]);
+  }
+}
+
+pub async fn handler() -> Response {
+  let mut buf = Vec::new();
+  if let Err(err) = TextEncoder::new().encode(&metrics().registry.gather(), &mut buf) {
+    log::error!("Failed to encode metrics: {err:?}");
+    return (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "Failed to encode metrics",
+    )
+      .into_response();
+  }
+  ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], buf).into_response()
+}
@@ -16,7 +16,7 @@ pub struct Log {
   pub related_uid: Option<i64>,
 }
 
-#[derive(Insertable, Debug, Clone)]
+#[derive(Insertable, Debug, Clone, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::logs)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct NewLog {
@@ -24,5 +24,154 @@ pub struct NewLog {
   pub command: String,
   pub raw_json: Value,
   pub time: chrono::DateTime<Utc>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub related_uid: Option<i64>,
+}
+
+/// [`Log`] in SQLite, see [`crate::schema_sqlite`]
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = crate::schema_sqlite::logs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SqliteLog {
+  pub id: i64,
+  pub room_id: i64,
+  pub command: String,
+  pub raw_json: String,
+  pub time: chrono::DateTime<Utc>,
+  pub related_uid: Option<i64>,
+}
+
+impl TryFrom<SqliteLog> for Log {
+  type Error = serde_json::Error;
+
+  fn try_from(log: SqliteLog) -> Result<Self, Self::Error> {
+    Ok(Log {
+      id: log.id,
+      room_id: log.room_id,
+      command: log.command,
+      raw_json: serde_json::from_str(&log.raw_json)?,
+      time: log.time,
+      related_uid: log.related_uid,
+    })
+  }
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema_sqlite::logs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewSqliteLog {
+  pub room_id: i64,
+  pub command: String,
+  pub raw_json: String,
+  pub time: chrono::DateTime<Utc>,
   pub related_uid: Option<i64>,
 }
+
+impl From<&NewLog> for NewSqliteLog {
+  fn from(log: &NewLog) -> Self {
+    NewSqliteLog {
+      room_id: log.room_id,
+      command: log.command.clone(),
+      raw_json: log.raw_json.to_string(),
+      time: log.time,
+      related_uid: log.related_uid,
+    }
+  }
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::rooms)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewRoom {
+  pub room_id: i64,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Session {
+  pub id: i64,
+  pub room_id: i64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub live_key: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sub_session_key: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub platform: Option<String>,
+  pub start_time: chrono::DateTime<Utc>,
+  /// `None` if the session is still ongoing
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub end_time: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewSession {
+  pub room_id: i64,
+  pub live_key: Option<String>,
+  pub sub_session_key: Option<String>,
+  pub platform: Option<String>,
+  pub start_time: chrono::DateTime<Utc>,
+}
+
+// Typed rows below are inserted along with `(log_id, room_id, time)` of the raw log, see `crate::typed`
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::danmaku)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewDanmaku {
+  pub uid: i64,
+  pub username: String,
+  pub content: String,
+  pub mode: i16,
+  pub is_emoticon: bool,
+  pub medal_name: Option<String>,
+  pub medal_level: Option<i32>,
+  pub medal_liver_uid: Option<i64>,
+  pub medal_guard_level: Option<i16>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::super_chats)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewSuperChat {
+  pub id: i64,
+  pub uid: i64,
+  pub username: String,
+  /// CNY
+  pub price: i32,
+  pub message: Option<String>,
+  pub translation: Option<String>,
+  pub duration_secs: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::guard_buys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewGuardBuy {
+  pub uid: i64,
+  pub username: String,
+  pub guard_level: i16,
+  /// Gold coins of a single unit
+  pub price: i64,
+  pub num: i32,
+  pub gift_name: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::gifts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewGift {
+  pub uid: i64,
+  pub username: String,
+  pub gift_id: i64,
+  pub gift_name: String,
+  pub action: String,
+  pub num: i32,
+  pub coin_type: String,
+  /// Coins of a single gift
+  pub price: i64,
+  pub total_coin: i64,
+}
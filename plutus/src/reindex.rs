@@ -0,0 +1,150 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use plutus_core::data::live::cmds::MaybeCommand;
+
+use crate::{
+  error::AnyhowWrapper, models::Log, related_uid, schema::logs, state::State, typed::TypedRow,
+};
+
+#[derive(Parser, Debug)]
+pub struct ReindexCommand {
+  /// Only reindex logs with id greater than this, the checkpoint is used if not set
+  #[arg(long)]
+  pub after: Option<i64>,
+  /// Only reindex logs of this room
+  #[arg(short, long)]
+  pub room: Option<u64>,
+  /// Logs read and updated per transaction
+  #[clap(long, default_value = "1000")]
+  pub batch_size: i64,
+  /// The last processed id is saved here after each batch, so an interrupted run can be resumed.
+  /// Defaults to `reindex.checkpoint`, or `reindex-{room}.checkpoint` with `--room`.
+  #[clap(long)]
+  pub checkpoint: Option<PathBuf>,
+}
+
+impl ReindexCommand {
+  /// Runs of a single room don't advance the checkpoint of every room
+  fn checkpoint(&self) -> PathBuf {
+    match (&self.checkpoint, self.room) {
+      (Some(path), _) => path.clone(),
+      (None, Some(room_id)) => PathBuf::from(format!("reindex-{room_id}.checkpoint")),
+      (None, None) => PathBuf::from("reindex.checkpoint"),
+    }
+  }
+}
+
+/// Parses saved logs again with the current [`MaybeCommand`] model,
+/// filling `related_uid` and typed tables of logs saved before they were supported.
+pub async fn reindex(args: ReindexCommand) -> anyhow::Result<()> {
+  let state = State::init().await.context("Failed to init plutus")?;
+  if !state.is_postgres() {
+    bail!("Reindex is only available with PostgreSQL");
+  }
+  let mut conn = state.db_con().await.map_err(AnyhowWrapper::into_inner)?;
+
+  let checkpoint = args.checkpoint();
+  let mut last_id = match args.after {
+    Some(after) => after,
+    None => read_checkpoint(&checkpoint)?,
+  };
+  log::info!("Reindexing logs after id {last_id}");
+
+  let batch_size = args.batch_size.max(1);
+  let (mut processed, mut updated, mut typed) = (0, 0, 0);
+  loop {
+    let mut query = logs::table
+      .filter(logs::id.gt(last_id))
+      .order_by(logs::id)
+      .limit(batch_size)
+      .select(Log::as_select())
+      .into_boxed();
+    if let Some(room_id) = args.room {
+      query = query.filter(logs::room_id.eq(room_id as i64));
+    }
+    let batch: Vec<Log> = query
+      .load(&mut conn)
+      .await
+      .with_context(|| format!("Failed to read logs after id {last_id}"))?;
+    let Some(last) = batch.last() else {
+      break;
+    };
+    let batch_last_id = last.id;
+
+    let mut uids = Vec::new();
+    let mut rows = Vec::new();
+    for log in &batch {
+      let Ok(MaybeCommand::Command(cmd)) =
+        serde_json::from_value::<MaybeCommand>(log.raw_json.clone())
+      else {
+        continue;
+      };
+      let uid = related_uid(&cmd);
+      if uid.is_some() && uid != log.related_uid {
+        uids.push((log.id, log.time, uid));
+      }
+      if let Some(row) = TypedRow::from_command(&cmd) {
+        rows.push((log.id, log.room_id, log.time, row));
+      }
+    }
+
+    let (batch_updated, batch_typed) = (uids.len(), rows.len());
+    conn
+      .transaction(|conn| {
+        async move {
+          for (id, time, uid) in uids {
+            diesel::update(logs::table.find((id, time)))
+              .set(logs::related_uid.eq(uid))
+              .execute(conn)
+              .await?;
+          }
+          let rows: Vec<_> = rows
+            .iter()
+            .map(|(id, room_id, time, row)| (*id, *room_id, *time, row))
+            .collect();
+          crate::typed::insert_or_skip(conn, &rows).await
+        }
+        .scope_boxed()
+      })
+      .await
+      .with_context(|| format!("Failed to reindex logs up to id {batch_last_id}"))?;
+
+    last_id = batch_last_id;
+    write_checkpoint(&checkpoint, last_id)?;
+    processed += batch.len();
+    updated += batch_updated;
+    typed += batch_typed;
+    log::info!(
+      "Reindexed {processed} logs up to id {last_id}, \
+       {updated} related_uid updated, {typed} typed rows"
+    );
+  }
+
+  log::info!("Reindex finished at id {last_id}, {processed} logs processed");
+  Ok(())
+}
+
+fn read_checkpoint(path: &Path) -> anyhow::Result<i64> {
+  match fs::read_to_string(path) {
+    Ok(content) => content
+      .trim()
+      .parse()
+      .with_context(|| format!("Malformed checkpoint `{}`", path.to_string_lossy())),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+    Err(err) => {
+      Err(err).with_context(|| format!("Failed to read checkpoint `{}`", path.to_string_lossy()))
+    },
+  }
+}
+
+fn write_checkpoint(path: &Path, last_id: i64) -> anyhow::Result<()> {
+  fs::write(path, last_id.to_string())
+    .with_context(|| format!("Failed to write checkpoint `{}`", path.to_string_lossy()))
+}
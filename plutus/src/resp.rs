@@ -52,6 +52,9 @@ impl<T: Serialize> IntoResponse for Resp<T> {
 pub struct Paginated<T> {
   pub page: Page,
   pub list: Vec<T>,
+  /// Opaque cursor of the next page, pass it as `cursor.after`. Absent on the last page.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub next: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -68,6 +71,9 @@ pub struct Cursor {
   pub page: NonZeroU64,
   #[serde(default = "Cursor::default_page_size")]
   pub size: NonZeroU64,
+  /// `next` of the previous page, continues after it by keyset and `page` is ignored
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub after: Option<String>,
 }
 
 impl Cursor {
@@ -86,6 +92,7 @@ impl Default for Cursor {
     Self {
       page: Self::default_current_page(),
       size: Self::default_page_size(),
+      after: None,
     }
   }
 }
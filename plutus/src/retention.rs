@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeDelta, TimeZone, Utc};
+use diesel::{
+  sql_query,
+  sql_types::{Bool, Text, Timestamptz},
+  QueryableByName,
+};
+use diesel_async::RunQueryDsl;
+
+use crate::{
+  config::RetentionConfig, error::AnyhowWrapper, global_state, state::AsyncPoolConnection,
+  store::PruneScope,
+};
+
+#[derive(QueryableByName)]
+struct Created {
+  #[diesel(sql_type = Bool)]
+  created: bool,
+}
+
+#[derive(QueryableByName)]
+struct Partition {
+  #[diesel(sql_type = Text)]
+  name: String,
+}
+
+/// Monthly partitions of `logs`, the legacy and default partitions are never dropped
+const PARTITIONS_SQL: &str = r#"
+SELECT child.relname::TEXT AS name
+FROM pg_inherits
+JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+WHERE pg_inherits.inhparent = 'logs'::regclass AND child.relname ~ '^logs_y\d{4}m\d{2}$'
+"#;
+
+/// Creates partitions of the coming months and prunes logs past their retention, every interval.
+pub async fn run(config: RetentionConfig) {
+  let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+  loop {
+    interval.tick().await;
+    #[allow(clippy::collapsible_if)]
+    if global_state().is_postgres() {
+      if let Err(err) = maintain_partitions(&config).await {
+        log::error!("Failed to maintain partitions of logs: {err:#}");
+      }
+    }
+    if let Err(err) = prune(&config).await {
+      log::error!("Failed to prune logs: {err:#}");
+    }
+  }
+}
+
+/// Creates partitions of this and next month, then drops months older than every rule
+async fn maintain_partitions(config: &RetentionConfig) -> anyhow::Result<()> {
+  let conn: &mut AsyncPoolConnection = &mut global_state()
+    .db_con()
+    .await
+    .map_err(AnyhowWrapper::into_inner)?;
+  let this_month = month_start(Utc::now());
+  for month in [this_month, this_month + Months::new(1)] {
+    let created: Created = sql_query("SELECT create_logs_partition($1) AS created")
+      .bind::<Timestamptz, _>(month)
+      .get_result(conn)
+      .await
+      .with_context(|| format!("Failed to create partition of {}", month.format("%Y-%m")))?;
+    if created.created {
+      log::info!("Created partition of logs for {}", month.format("%Y-%m"));
+    }
+  }
+
+  // Commands without a rule are kept forever unless `default-days` is set
+  let Some(default_days) = config.default_days else {
+    return Ok(());
+  };
+  let max_days = config
+    .commands
+    .values()
+    .copied()
+    .fold(default_days, u32::max);
+  let cutoff = Utc::now() - TimeDelta::days(max_days as i64);
+  let partitions: Vec<Partition> = sql_query(PARTITIONS_SQL)
+    .load(conn)
+    .await
+    .context("Failed to list partitions of logs")?;
+  for Partition { name } in partitions {
+    let Ok(start) = NaiveDate::parse_from_str(&format!("{name}d01"), "logs_y%Ym%md%d") else {
+      continue;
+    };
+    let end = Utc.from_utc_datetime(&start.and_hms_opt(0, 0, 0).unwrap()) + Months::new(1);
+    if end > cutoff {
+      continue;
+    }
+    // The name is checked by the pattern above, so it needs no quoting
+    sql_query(format!("DROP TABLE {name}"))
+      .execute(conn)
+      .await
+      .with_context(|| format!("Failed to drop partition {name}"))?;
+    log::info!("Dropped partition {name} of logs before {cutoff}");
+  }
+  Ok(())
+}
+
+/// Deletes logs of each command rule, then logs of other commands by the default rule
+async fn prune(config: &RetentionConfig) -> anyhow::Result<()> {
+  let now = Utc::now();
+  for (command, days) in &config.commands {
+    prune_scope(PruneScope::Command(command), now, *days, config.batch_size).await?;
+  }
+  if let Some(days) = config.default_days {
+    let commands: Vec<String> = config.commands.keys().cloned().collect();
+    prune_scope(PruneScope::Except(&commands), now, days, config.batch_size).await?;
+  }
+  Ok(())
+}
+
+async fn prune_scope(
+  scope: PruneScope<'_>,
+  now: DateTime<Utc>,
+  days: u32,
+  batch_size: i64,
+) -> anyhow::Result<()> {
+  let before = now - TimeDelta::days(days as i64);
+  let mut total = 0;
+  loop {
+    let deleted = global_state()
+      .store()
+      .prune(scope, before, batch_size.max(1))
+      .await
+      .with_context(|| format!("Failed to prune {scope:?} before {before}"))?;
+    if deleted == 0 {
+      break;
+    }
+    total += deleted;
+  }
+  if total > 0 {
+    log::info!("Pruned {total} logs of {scope:?} before {before}");
+  }
+  Ok(())
+}
+
+fn month_start(time: DateTime<Utc>) -> DateTime<Utc> {
+  Utc
+    .with_ymd_and_hms(time.year(), time.month(), 1, 0, 0, 0)
+    .unwrap()
+}
@@ -0,0 +1,361 @@
+use std::{
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+  extract::Path,
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use chrono::{DateTime, Utc};
+use dashmap::Entry;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use plutus_core::{api::live::HeartbeatInfo, client::Client};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{
+  app_err, collect_room,
+  error::{AnyhowExt, AppResp, IntoAppResult},
+  global_state, metrics,
+  models::NewRoom,
+  resp::{AppCode, Resp},
+  room_registry,
+  schema::rooms,
+  state::AsyncPoolConnection,
+  ADashMap,
+};
+
+pub type SharedRoomStatus = Arc<RwLock<RoomStatus>>;
+
+/// A connected room is considered unhealthy if no heartbeat response is received
+/// within this duration, the heartbeat interval is 30s.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RoomStatus {
+  pub state: ConnState,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub connected_since: Option<DateTime<Utc>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub last_message_time: Option<DateTime<Utc>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub last_heartbeat_time: Option<DateTime<Utc>>,
+  /// Popularity in the last heartbeat response
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub popularity: Option<u32>,
+  pub reconnect_count: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub last_error: Option<RoomError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomError {
+  pub message: String,
+  pub time: DateTime<Utc>,
+}
+
+impl RoomStatus {
+  pub fn connecting(&mut self) {
+    if self.state == ConnState::Disconnected {
+      self.reconnect_count += 1;
+    }
+    self.state = ConnState::Connecting;
+  }
+
+  pub fn connected(&mut self) {
+    self.state = ConnState::Connected;
+    self.connected_since = Some(Utc::now());
+  }
+
+  pub fn disconnected(&mut self, error: String) {
+    self.state = ConnState::Disconnected;
+    self.connected_since = None;
+    self.last_error = Some(RoomError {
+      message: error,
+      time: Utc::now(),
+    });
+  }
+
+  pub fn received(&mut self) {
+    self.last_message_time = Some(Utc::now());
+  }
+
+  pub fn heartbeat(&mut self, heartbeat: HeartbeatInfo) {
+    self.last_heartbeat_time = Some(heartbeat.received_at.into());
+    self.popularity = Some(heartbeat.popular);
+  }
+
+  pub fn is_healthy(&self) -> bool {
+    if self.state != ConnState::Connected {
+      return false;
+    }
+    let timeout = chrono::Duration::from_std(HEARTBEAT_TIMEOUT).unwrap();
+    self
+      .last_heartbeat_time
+      .or(self.connected_since)
+      .is_some_and(|time| Utc::now() - time <= timeout)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnState {
+  #[default]
+  Connecting,
+  Connected,
+  Disconnected,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomInfo {
+  pub room_id: u64,
+  pub healthy: bool,
+  #[serde(flatten)]
+  pub status: RoomStatus,
+}
+
+impl RoomInfo {
+  fn new(room_id: u64, status: RoomStatus) -> RoomInfo {
+    RoomInfo {
+      room_id,
+      healthy: status.is_healthy(),
+      status,
+    }
+  }
+}
+
+struct RoomHandle {
+  job: JoinHandle<()>,
+  status: SharedRoomStatus,
+}
+
+/// Per-room collectors, which can be started and stopped at runtime
+pub struct RoomRegistry {
+  client: Client,
+  rooms: ADashMap<u64, RoomHandle>,
+}
+
+impl RoomRegistry {
+  pub fn new(client: Client) -> RoomRegistry {
+    RoomRegistry {
+      client,
+      rooms: ADashMap::default(),
+    }
+  }
+
+  pub fn client(&self) -> &Client {
+    &self.client
+  }
+
+  /// Starts collecting the room, returns `false` if it is already running
+  pub fn start(&self, room_id: u64) -> bool {
+    match self.rooms.entry(room_id) {
+      Entry::Occupied(_) => false,
+      Entry::Vacant(entry) => {
+        let status = SharedRoomStatus::default();
+        let job = tokio::spawn(collect_room(
+          self.client.clone(),
+          room_id,
+          Arc::clone(&status),
+        ));
+        entry.insert(RoomHandle { job, status });
+        true
+      },
+    }
+  }
+
+  /// Stops collecting the room, returns `false` if it is not running
+  pub fn stop(&self, room_id: u64) -> bool {
+    match self.rooms.remove(&room_id) {
+      Some((_, handle)) => {
+        handle.job.abort();
+        metrics().remove_room(room_id);
+        true
+      },
+      None => false,
+    }
+  }
+
+  pub fn status(&self, room_id: u64) -> Option<RoomStatus> {
+    self
+      .rooms
+      .get(&room_id)
+      .map(|handle| handle.status.read().unwrap().clone())
+  }
+
+  pub fn list(&self) -> Vec<RoomInfo> {
+    let mut rooms: Vec<RoomInfo> = self
+      .rooms
+      .iter()
+      .map(|entry| RoomInfo::new(*entry.key(), entry.value().status.read().unwrap().clone()))
+      .collect();
+    rooms.sort_unstable_by_key(|room| room.room_id);
+    rooms
+  }
+}
+
+pub fn update_status(status: &SharedRoomStatus, f: impl FnOnce(&mut RoomStatus)) {
+  f(&mut status.write().unwrap());
+}
+
+/// Real room id of a short id, `None` if there is no such room
+async fn resolve_room_id(room_id: u64) -> anyhow::Result<Option<u64>> {
+  let resp = room_registry()
+    .client()
+    .live()
+    .init_room(&room_id.into())
+    .await
+    .context("Failed to init room")?;
+  Ok(resp.data.and_then(|data| data.room_id))
+}
+
+/// Starts all rooms saved in database, rooms in config are saved first unless saved before,
+/// so a room removed by [`remove`] is not started again.
+/// Rooms in config are resolved to real room ids like [`add`], in case some are short ids.
+/// Only rooms in config are started if rooms can't be saved, see [`crate::state::State::is_postgres`].
+pub async fn start_all(config_rooms: &[u64]) -> anyhow::Result<()> {
+  let mut resolved = Vec::with_capacity(config_rooms.len());
+  for &room_id in config_rooms {
+    match resolve_room_id(room_id).await {
+      Ok(Some(real_id)) => resolved.push(real_id),
+      Ok(None) => log::warn!("Room {room_id} in config does not exist, skipped"),
+      Err(err) => {
+        log::warn!("Failed to resolve room {room_id} in config, started as is: {err:#}");
+        resolved.push(room_id);
+      },
+    }
+  }
+  let config_rooms = resolved;
+
+  if !global_state().is_postgres() {
+    for room_id in config_rooms {
+      room_registry().start(room_id);
+    }
+    return Ok(());
+  }
+  let mut conn = global_state().db_con().await?;
+  let new_rooms: Vec<NewRoom> = config_rooms
+    .iter()
+    .map(|room_id| NewRoom {
+      room_id: *room_id as i64,
+    })
+    .collect();
+  diesel::insert_into(rooms::table)
+    .values(&new_rooms)
+    .on_conflict_do_nothing()
+    .execute(&mut conn)
+    .await
+    .context("Failed to save rooms in config")?;
+
+  let rooms: Vec<(i64, bool)> = rooms::table
+    .select((rooms::room_id, rooms::removed_at.is_not_null()))
+    .order_by(rooms::room_id)
+    .load(&mut conn)
+    .await
+    .context("Failed to load rooms")?;
+  for (room_id, removed) in rooms {
+    if !removed {
+      room_registry().start(room_id as u64);
+    } else if config_rooms.contains(&(room_id as u64)) {
+      log::warn!("Room {room_id} in config was removed, add it again to collect it");
+    }
+  }
+  Ok(())
+}
+
+pub async fn list() -> AppResp<Vec<RoomInfo>> {
+  Ok(Resp::new_success(room_registry().list()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AddRoomBody {
+  /// Real room id or short id
+  pub room_id: u64,
+}
+
+pub async fn add(Json(body): Json<AddRoomBody>) -> AppResp<RoomInfo> {
+  let room_id = resolve_room_id(body.room_id)
+    .await
+    .also_log()
+    .ok()
+    .flatten()
+    .ok_or_else(|| app_err!(AppCode::INVALID_ARGUMENTS, "No such room {}", body.room_id))?;
+
+  if global_state().is_postgres() {
+    let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+    diesel::insert_into(rooms::table)
+      .values(&NewRoom {
+        room_id: room_id as i64,
+      })
+      .on_conflict(rooms::room_id)
+      .do_update()
+      .set(rooms::removed_at.eq(None::<DateTime<Utc>>))
+      .execute(conn)
+      .await
+      .context_into_app("Failed to save room")?;
+  }
+
+  if room_registry().start(room_id) {
+    log::info!("Room {room_id} added");
+  }
+  Ok(Resp::new_success(RoomInfo::new(
+    room_id,
+    room_registry().status(room_id).unwrap_or_default(),
+  )))
+}
+
+pub async fn remove(Path(room_id): Path<u64>) -> AppResp {
+  let deleted = if global_state().is_postgres() {
+    let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+    // Kept, so it is not saved again from config
+    diesel::update(rooms::table)
+      .filter(rooms::room_id.eq(room_id as i64))
+      .filter(rooms::removed_at.is_null())
+      .set(rooms::removed_at.eq(diesel::dsl::now))
+      .execute(conn)
+      .await
+      .context_into_app("Failed to delete room")?
+  } else {
+    0
+  };
+
+  let stopped = room_registry().stop(room_id);
+  if deleted == 0 && !stopped {
+    return Err(app_err!(
+      AppCode::INVALID_ARGUMENTS,
+      "No such room {room_id}"
+    ));
+  }
+  log::info!("Room {room_id} removed");
+  Ok(Resp::new_success(()))
+}
+
+pub async fn status(Path(room_id): Path<u64>) -> AppResp<RoomInfo> {
+  let status = room_registry()
+    .status(room_id)
+    .ok_or_else(|| app_err!(AppCode::INVALID_ARGUMENTS, "No such room {room_id}"))?;
+  Ok(Resp::new_success(RoomInfo::new(room_id, status)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Health {
+  pub healthy: bool,
+  pub rooms: Vec<RoomInfo>,
+}
+
+/// Responds `503 Service Unavailable` if any room is unhealthy, for alerting
+pub async fn health() -> Response {
+  let rooms = room_registry().list();
+  let healthy = rooms.iter().all(|room| room.healthy);
+  let code = if healthy {
+    StatusCode::OK
+  } else {
+    StatusCode::SERVICE_UNAVAILABLE
+  };
+  (code, Resp::new_success(Health { healthy, rooms })).into_response()
+}
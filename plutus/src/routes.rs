@@ -4,11 +4,15 @@ use anyhow::Context;
 use axum::{
   http::StatusCode,
   response::{IntoResponse, Response},
-  routing::{get, post},
+  routing::{delete, get, post},
   Json, Router,
 };
 use chrono::{DateTime, Utc};
-use diesel::{pg::Pg, ExpressionMethods, QueryDsl};
+use diesel::{
+  dsl::sql,
+  result::Error as DieselError,
+  sql_types::{Bool, Text},
+};
 use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
@@ -16,19 +20,37 @@ use tower_http::{compression::CompressionLayer, timeout::TimeoutLayer};
 
 use crate::{
   app_err,
-  error::{AppResp, IntoAppResult},
-  global_state,
+  error::{AppResp, AppResult, IntoAppResult},
+  export, feed, global_state, metrics,
   models::Log,
   resp::{AppCode, Cursor, Page, Paginated, Resp},
-  schema::logs,
-  state::AsyncPoolConnection,
+  rooms, sessions,
+  state::{AsyncPoolConnection, State},
+  stats,
+  store::{self, LogFilter, LogKey},
   PLUTUS_VERSION,
 };
 
+/// Page size limit of `/list` with OFFSET
+const MAX_PAGE_SIZE: u64 = 1000;
+/// Page size limit of `/list` without OFFSET, i.e. the first page or with `cursor.after`
+const MAX_KEYSET_PAGE_SIZE: u64 = 10000;
+
 pub async fn server(addr: &SocketAddr) -> anyhow::Result<()> {
   let router = Router::new()
     .route("/", get(index))
     .route("/list", post(list))
+    .route("/export", post(export::export))
+    .route("/stats/commands", post(stats::commands))
+    .route("/stats/revenue", post(stats::revenue))
+    .route("/health", get(rooms::health))
+    .route("/metrics", get(metrics::handler))
+    .route("/rooms", get(rooms::list).post(rooms::add))
+    .route("/rooms/{id}", delete(rooms::remove))
+    .route("/rooms/{id}/status", get(rooms::status))
+    .route("/rooms/{id}/sessions", get(sessions::list))
+    .route("/feed/sse", get(feed::sse))
+    .route("/feed/ws", get(feed::ws))
     .fallback(get(fallback))
     .layer(TimeoutLayer::new(Duration::from_secs(5 * 60)))
     .layer(CompressionLayer::new());
@@ -62,11 +84,32 @@ pub struct QueryBody {
   pub uid: Option<u64>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub time_range: Option<TimeRange>,
+  /// Only logs during the session, see `/rooms/{id}/sessions`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub session: Option<i64>,
+  /// Counts the total pages, disable it for large rooms as it scans every matched log
+  #[serde(default = "QueryBody::default_count")]
+  pub count: bool,
+  /// Danmaku or SuperChat containing the keyword, case-insensitive.
+  /// Keywords shorter than 3 characters, or CJK ones if the database doesn't have a UTF-8 LC_CTYPE,
+  /// can't use the trigram index and scan every message of the room.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub keyword: Option<String>,
+  /// Danmaku or SuperChat matching the POSIX regular expression, indexed like `keyword`
+  /// by the literal parts of at least 3 characters
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub regex: Option<String>,
   #[serde(default)]
   pub cursor: Cursor,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+impl QueryBody {
+  fn default_count() -> bool {
+    true
+  }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy)]
 pub struct TimeRange {
   #[serde(skip_serializing_if = "Option::is_none")]
   pub start: Option<DateTime<Utc>>,
@@ -74,70 +117,180 @@ pub struct TimeRange {
   pub end: Option<DateTime<Utc>>,
 }
 
-async fn list(Json(body): Json<QueryBody>) -> AppResp<Paginated<Log>> {
-  let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
-
-  fn new_query(
-    body: &QueryBody,
-  ) -> diesel::query_builder::BoxedSelectStatement<
-    '_,
-    logs::SqlType,
-    diesel::query_builder::FromClause<logs::table>,
-    Pg,
-  > {
-    let mut query = logs::table
-      .into_boxed()
-      .filter(logs::room_id.eq(body.room_id as i64));
-    if !body.commands.is_empty() {
-      query = query.filter(logs::command.eq_any(&body.commands));
-    }
-    if let Some(uid) = body.uid {
-      query = query.filter(logs::related_uid.eq(uid as i64));
-    }
-    if let Some(TimeRange { start, end }) = body.time_range {
-      if let Some(start) = start {
-        query = query.filter(logs::time.ge(start));
-      }
-      if let Some(end) = end {
-        query = query.filter(logs::time.le(end));
-      }
+/// Text search relies on the typed tables and trigram indexes of PostgreSQL.
+/// The regex is compiled by PostgreSQL first, so an invalid one is reported as an invalid argument.
+pub async fn check_text_search(state: &State, filter: &LogFilter) -> AppResult<()> {
+  if filter.has_text_search() && !state.is_postgres() {
+    return Err(app_err!(
+      AppCode::INVALID_ARGUMENTS,
+      "Keyword and regex are only available with PostgreSQL"
+    ));
+  }
+  if let Some(ref regex) = filter.regex {
+    let conn: &mut AsyncPoolConnection = &mut state.db_con().await?;
+    let result = diesel::select(sql::<Bool>("'' ~ ").bind::<Text, _>(regex))
+      .get_result::<bool>(conn)
+      .await;
+    match result {
+      Ok(_) => {},
+      Err(err) if store::is_unavailable(&err) => {
+        return Err(err).context_into_app("Failed to check regex");
+      },
+      Err(DieselError::DatabaseError(_, info)) => {
+        return Err(app_err!(
+          AppCode::INVALID_ARGUMENTS,
+          "Invalid regex: {}",
+          info.message()
+        ));
+      },
+      Err(err) => return Err(err).context_into_app("Failed to check regex"),
     }
-    query
   }
+  Ok(())
+}
+
+async fn list(Json(body): Json<QueryBody>) -> AppResp<Paginated<Log>> {
+  query_logs(global_state(), body).await
+}
 
-  let offset = (body.cursor.page.get().sub(1) * body.cursor.size.get()) as i64;
-  let limit = body.cursor.size.get() as i64;
+async fn query_logs(state: &State, body: QueryBody) -> AppResp<Paginated<Log>> {
+  let session_range = match body.session {
+    Some(session_id) => {
+      let conn: &mut AsyncPoolConnection = &mut state.db_con().await?;
+      Some(sessions::time_range(conn, body.room_id, session_id).await?)
+    },
+    None => None,
+  };
 
-  let count: i64 = new_query(&body)
-    .count()
-    .get_result::<i64>(conn)
-    .await
-    .context_into_app("Failed to count columns size")?;
+  let after = match body.cursor.after {
+    Some(ref after) => Some(
+      LogKey::decode(after)
+        .ok_or_else(|| app_err!(AppCode::INVALID_ARGUMENTS, "Invalid cursor"))?,
+    ),
+    None => None,
+  };
+  // OFFSET scans every skipped row, larger pages are only allowed without it
+  let page = body.cursor.page.get();
+  let size = body.cursor.size.get();
+  let max_size = if after.is_some() || page == 1 {
+    MAX_KEYSET_PAGE_SIZE
+  } else {
+    MAX_PAGE_SIZE
+  };
+  if size > max_size {
+    return Err(app_err!(AppCode::INVALID_ARGUMENTS, "Invalid cursor"));
+  }
+  let offset = match after {
+    Some(_) => 0,
+    None => (page.sub(1) * size) as i64,
+  };
 
-  let max = (count as u64).div_ceil(body.cursor.size.get());
+  let filter = LogFilter::new(&body, session_range.as_ref());
+  check_text_search(state, &filter).await?;
+  let max = match body.count {
+    true => {
+      let count: i64 = state
+        .store()
+        .count(&filter)
+        .await
+        .context_into_app("Failed to count columns size")?;
+      Some((count as u64).div_ceil(size))
+    },
+    false => None,
+  };
 
   #[allow(clippy::collapsible_if)]
-  if max != 0 {
-    if !(1..=max).contains(&body.cursor.page.get()) || !(1..=1000).contains(&body.cursor.size.get())
-    {
+  if let (Some(max), None) = (max, after) {
+    if max != 0 && !(1..=max).contains(&page) {
       return Err(app_err!(AppCode::INVALID_ARGUMENTS, "Invalid cursor"));
     }
   }
 
-  let logs: Vec<Log> = new_query(&body)
-    .limit(limit)
-    .offset(offset)
-    .order_by(logs::time)
-    .get_results(conn)
+  let logs: Vec<Log> = state
+    .store()
+    .list(&filter, after.as_ref(), offset, size as i64)
     .await
     .context_into_app("Failed to query logs")?;
+  let next = match logs.last() {
+    Some(last) if logs.len() as u64 == size => Some(LogKey::of(last).encode()),
+    _ => None,
+  };
 
   Ok(Resp::new_success(Paginated {
     page: Page {
-      current: body.cursor.page.get(),
-      max: Some(max),
+      current: page,
+      max,
       size: logs.len() as u64,
     },
     list: logs,
+    next,
   }))
 }
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use chrono::TimeZone;
+  use serde_json::json;
+
+  use super::*;
+  use crate::{
+    config::Config,
+    models::NewLog,
+    store::{LogStore, MemoryStore},
+  };
+
+  #[tokio::test]
+  async fn list_from_memory_store() {
+    let store = Arc::new(MemoryStore::default());
+    let log = |command: &str, uid: Option<i64>, secs: i64| NewLog {
+      room_id: 1,
+      command: command.to_string(),
+      raw_json: json!({ "cmd": command }),
+      time: Utc.timestamp_opt(1700000000 + secs, 0).unwrap(),
+      related_uid: uid,
+    };
+    let logs = [
+      log("DANMU_MSG", Some(2), 2),
+      log("DANMU_MSG", Some(3), 1),
+      log("LIVE", None, 0),
+      log("SEND_GIFT", Some(2), 3),
+    ];
+    assert!(store
+      .insert(logs.iter().map(|log| (log, None)).collect())
+      .await
+      .is_ok());
+
+    let config: Config = toml::from_str(r#"database-url = "memory:""#).unwrap();
+    let state = State::new(config, None, store);
+
+    let body: QueryBody = serde_json::from_value(json!({
+      "room_id": 1,
+      "commands": ["DANMU_MSG", "SEND_GIFT"],
+      "cursor": { "page": 1, "size": 2 },
+    }))
+    .unwrap();
+    let page = query_logs(&state, body).await.unwrap().data.unwrap();
+    assert_eq!(page.page.max, Some(2));
+    let uids: Vec<_> = page.list.iter().map(|log| log.related_uid).collect();
+    assert_eq!(uids, [Some(3), Some(2)]);
+
+    let body: QueryBody = serde_json::from_value(json!({
+      "room_id": 1,
+      "count": false,
+      "cursor": { "size": 2, "after": page.next.unwrap() },
+    }))
+    .unwrap();
+    let page = query_logs(&state, body).await.unwrap().data.unwrap();
+    assert_eq!(page.page.max, None);
+    let commands: Vec<_> = page.list.iter().map(|log| log.command.as_str()).collect();
+    assert_eq!(commands, ["SEND_GIFT"]);
+    assert!(page.next.is_none());
+
+    let body: QueryBody = serde_json::from_value(json!({ "room_id": 1, "uid": 2 })).unwrap();
+    let page = query_logs(&state, body).await.unwrap().data.unwrap();
+    let commands: Vec<_> = page.list.iter().map(|log| log.command.as_str()).collect();
+    assert_eq!(commands, ["DANMU_MSG", "SEND_GIFT"]);
+  }
+}
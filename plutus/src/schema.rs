@@ -1,7 +1,63 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    logs (id) {
+    danmaku (log_id) {
+        log_id -> Int8,
+        room_id -> Int8,
+        time -> Timestamptz,
+        uid -> Int8,
+        #[max_length = 64]
+        username -> Varchar,
+        content -> Text,
+        mode -> Int2,
+        is_emoticon -> Bool,
+        #[max_length = 64]
+        medal_name -> Nullable<Varchar>,
+        medal_level -> Nullable<Int4>,
+        medal_liver_uid -> Nullable<Int8>,
+        medal_guard_level -> Nullable<Int2>,
+    }
+}
+
+diesel::table! {
+    gifts (log_id) {
+        log_id -> Int8,
+        room_id -> Int8,
+        time -> Timestamptz,
+        uid -> Int8,
+        #[max_length = 64]
+        username -> Varchar,
+        gift_id -> Int8,
+        #[max_length = 64]
+        gift_name -> Varchar,
+        #[max_length = 32]
+        action -> Varchar,
+        num -> Int4,
+        #[max_length = 16]
+        coin_type -> Varchar,
+        price -> Int8,
+        total_coin -> Int8,
+    }
+}
+
+diesel::table! {
+    guard_buys (log_id) {
+        log_id -> Int8,
+        room_id -> Int8,
+        time -> Timestamptz,
+        uid -> Int8,
+        #[max_length = 64]
+        username -> Varchar,
+        guard_level -> Int2,
+        price -> Int8,
+        num -> Int4,
+        #[max_length = 64]
+        gift_name -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    logs (id, time) {
         id -> Int8,
         room_id -> Int8,
         #[max_length = 128]
@@ -11,3 +67,52 @@ diesel::table! {
         related_uid -> Nullable<Int8>,
     }
 }
+
+diesel::table! {
+    rooms (room_id) {
+        room_id -> Int8,
+        created_at -> Timestamptz,
+        removed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Int8,
+        room_id -> Int8,
+        #[max_length = 128]
+        live_key -> Nullable<Varchar>,
+        #[max_length = 128]
+        sub_session_key -> Nullable<Varchar>,
+        #[max_length = 64]
+        platform -> Nullable<Varchar>,
+        start_time -> Timestamptz,
+        end_time -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    super_chats (log_id) {
+        log_id -> Int8,
+        room_id -> Int8,
+        time -> Timestamptz,
+        id -> Int8,
+        uid -> Int8,
+        #[max_length = 64]
+        username -> Varchar,
+        price -> Int4,
+        message -> Nullable<Text>,
+        translation -> Nullable<Text>,
+        duration_secs -> Int4,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+  danmaku,
+  gifts,
+  guard_buys,
+  logs,
+  rooms,
+  sessions,
+  super_chats,
+);
@@ -0,0 +1,12 @@
+// Tables of the SQLite backend, JSON is stored as text.
+
+diesel::table! {
+    logs (id) {
+        id -> BigInt,
+        room_id -> BigInt,
+        command -> Text,
+        raw_json -> Text,
+        time -> TimestamptzSqlite,
+        related_uid -> Nullable<BigInt>,
+    }
+}
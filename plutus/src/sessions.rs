@@ -0,0 +1,272 @@
+use std::num::NonZeroU64;
+
+use anyhow::Context;
+use axum::extract::{Path, Query};
+use chrono::{DateTime, Utc};
+use diesel::{
+  sql_query,
+  sql_types::{BigInt, Timestamptz},
+  ExpressionMethods, OptionalExtension, QueryDsl,
+};
+use diesel_async::RunQueryDsl;
+use plutus_core::{
+  client::Client,
+  data::live::{cmds::Command, LiveStatus},
+};
+use serde::Deserialize;
+
+use crate::{
+  app_err,
+  error::{AppResp, AppResult, IntoAppResult},
+  global_state,
+  models::{NewSession, Session},
+  resp::{AppCode, Resp},
+  routes::TimeRange,
+  schema::sessions,
+  state::AsyncPoolConnection,
+};
+
+/// Sessions of a room derived from `LIVE` / `PREPARING` logs in a time range, the same way as migration 2.
+/// Sessions already known, or overlapping a known one, are kept as they are.
+/// A session without its end in the range ends at the end of the range, or the next known session before it.
+const REBUILD_SQL: &str = r#"
+WITH events AS (
+  SELECT
+    command,
+    "time",
+    raw_json->>'live_key' AS live_key,
+    raw_json->>'sub_session_key' AS sub_session_key,
+    raw_json->>'live_platform' AS platform,
+    LAG(command) OVER w AS prev_command,
+    LAG(raw_json->>'live_key') OVER w AS prev_live_key
+  FROM logs
+  WHERE room_id = $1 AND command IN ('LIVE', 'PREPARING') AND "time" BETWEEN $2 AND $3
+  WINDOW w AS (ORDER BY "time", id)
+), starts AS (
+  SELECT * FROM events
+  WHERE command = 'LIVE'
+    AND (prev_command IS DISTINCT FROM 'LIVE' OR prev_live_key IS DISTINCT FROM live_key)
+)
+INSERT INTO sessions (room_id, live_key, sub_session_key, platform, start_time, end_time)
+SELECT
+  $1,
+  s.live_key,
+  s.sub_session_key,
+  s.platform,
+  s."time",
+  COALESCE(
+    LEAST(
+      (SELECT MIN(e."time") FROM events e WHERE e.command = 'PREPARING' AND e."time" > s."time"),
+      (SELECT MIN(n."time") FROM starts n WHERE n."time" > s."time")
+    ),
+    LEAST(
+      (SELECT MIN(k.start_time) FROM sessions k WHERE k.room_id = $1 AND k.start_time > s."time"),
+      $3
+    )
+  )
+FROM starts s
+WHERE NOT EXISTS (
+  SELECT 1 FROM sessions k
+  WHERE k.room_id = $1
+    AND (k.live_key = s.live_key
+      OR (k.start_time <= s."time" AND (k.end_time IS NULL OR k.end_time > s."time")))
+)
+"#;
+
+/// Whether the command is handled by [`on_command`]
+pub fn is_session_command(cmd: &Command) -> bool {
+  matches!(cmd, Command::Living { .. } | Command::Preparing { .. })
+}
+
+/// Opens or closes sessions according to `LIVE` and `PREPARING` commands.
+pub async fn on_command(
+  conn: &mut AsyncPoolConnection<'_>,
+  room_id: u64,
+  cmd: &Command,
+  time: DateTime<Utc>,
+) -> anyhow::Result<()> {
+  let room_id = room_id as i64;
+  match cmd {
+    Command::Living { data } => {
+      // LIVE is usually sent several times at the beginning of a broadcast,
+      // only a different live_key starts a new session.
+      diesel::update(sessions::table)
+        .filter(sessions::room_id.eq(room_id))
+        .filter(sessions::end_time.is_null())
+        .filter(sessions::live_key.is_not_null())
+        .filter(sessions::live_key.ne(&data.live_key))
+        .set(sessions::end_time.eq(time))
+        .execute(conn)
+        .await
+        .context("Failed to close previous session")?;
+      // Session opened by `sync_room` does not know its live_key
+      diesel::update(sessions::table)
+        .filter(sessions::room_id.eq(room_id))
+        .filter(sessions::end_time.is_null())
+        .filter(sessions::live_key.is_null())
+        .set((
+          sessions::live_key.eq(&data.live_key),
+          sessions::sub_session_key.eq(&data.sub_session_key),
+          sessions::platform.eq(&data.live_platform),
+        ))
+        .execute(conn)
+        .await
+        .context("Failed to update session")?;
+      open(
+        conn,
+        NewSession {
+          room_id,
+          live_key: Some(data.live_key.clone()),
+          sub_session_key: Some(data.sub_session_key.clone()),
+          platform: Some(data.live_platform.clone()),
+          start_time: time,
+        },
+      )
+      .await?;
+    },
+    Command::Preparing { data: _ } => {
+      close(conn, room_id, time).await?;
+    },
+    _ => {},
+  }
+  Ok(())
+}
+
+/// Reconciles sessions with the current room status, used when (re)connecting,
+/// since `LIVE` and `PREPARING` may be missed while disconnected.
+pub async fn sync_room(client: &Client, room_id: u64) -> anyhow::Result<()> {
+  if !global_state().is_postgres() {
+    return Ok(());
+  }
+  let data = client
+    .live()
+    .init_room(&room_id.into())
+    .await
+    .context("Failed to init room")?
+    .data
+    .context("InitRoomResp $.data is None")?;
+  let mut conn = global_state().db_con().await?;
+  let room_id = room_id as i64;
+
+  let live_time = data
+    .live_time
+    .filter(|ts| *ts > 0)
+    .and_then(|ts| DateTime::from_timestamp(ts, 0));
+  match (data.live_status, live_time) {
+    (Some(LiveStatus::Live), Some(live_time)) => {
+      open(
+        &mut conn,
+        NewSession {
+          room_id,
+          live_key: None,
+          sub_session_key: None,
+          platform: None,
+          start_time: live_time,
+        },
+      )
+      .await?;
+    },
+    (Some(LiveStatus::Live), None) => {},
+    _ => close(&mut conn, room_id, Utc::now()).await?,
+  }
+  Ok(())
+}
+
+/// Adds sessions of imported logs in `[start, end]`, which are not tracked when they are written.
+/// Returns the number of sessions added.
+pub async fn rebuild(
+  conn: &mut AsyncPoolConnection<'_>,
+  room_id: i64,
+  start: DateTime<Utc>,
+  end: DateTime<Utc>,
+) -> anyhow::Result<usize> {
+  sql_query(REBUILD_SQL)
+    .bind::<BigInt, _>(room_id)
+    .bind::<Timestamptz, _>(start)
+    .bind::<Timestamptz, _>(end)
+    .execute(conn)
+    .await
+    .with_context(|| format!("Failed to rebuild sessions of room {room_id}"))
+}
+
+async fn open(conn: &mut AsyncPoolConnection<'_>, new_session: NewSession) -> anyhow::Result<()> {
+  // At most one open session per room, see `sessions_room_id_open_idx`
+  diesel::insert_into(sessions::table)
+    .values(&new_session)
+    .on_conflict_do_nothing()
+    .execute(conn)
+    .await
+    .with_context(|| format!("Failed to open session: {new_session:?}"))?;
+  Ok(())
+}
+
+async fn close(
+  conn: &mut AsyncPoolConnection<'_>,
+  room_id: i64,
+  time: DateTime<Utc>,
+) -> anyhow::Result<()> {
+  diesel::update(sessions::table)
+    .filter(sessions::room_id.eq(room_id))
+    .filter(sessions::end_time.is_null())
+    .set(sessions::end_time.eq(time))
+    .execute(conn)
+    .await
+    .context("Failed to close session")?;
+  Ok(())
+}
+
+/// Time range of a session, for scoping other queries
+pub async fn time_range(
+  conn: &mut AsyncPoolConnection<'_>,
+  room_id: u64,
+  session_id: i64,
+) -> AppResult<TimeRange> {
+  let session: Option<Session> = sessions::table
+    .filter(sessions::id.eq(session_id))
+    .filter(sessions::room_id.eq(room_id as i64))
+    .first(conn)
+    .await
+    .optional()
+    .context_into_app("Failed to query session")?;
+  let session = session.ok_or_else(|| {
+    app_err!(
+      AppCode::INVALID_ARGUMENTS,
+      "No such session {session_id} in room {room_id}"
+    )
+  })?;
+  Ok(TimeRange {
+    start: Some(session.start_time),
+    end: session.end_time,
+  })
+}
+
+#[derive(Deserialize)]
+pub struct SessionsQuery {
+  #[serde(default = "SessionsQuery::default_limit")]
+  pub limit: NonZeroU64,
+}
+
+impl SessionsQuery {
+  #[inline]
+  fn default_limit() -> NonZeroU64 {
+    NonZeroU64::new(100).unwrap()
+  }
+}
+
+pub async fn list(
+  Path(room_id): Path<u64>,
+  Query(query): Query<SessionsQuery>,
+) -> AppResp<Vec<Session>> {
+  if !(1..=1000).contains(&query.limit.get()) {
+    return Err(app_err!(AppCode::INVALID_ARGUMENTS, "Invalid limit"));
+  }
+  let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+  let sessions: Vec<Session> = sessions::table
+    .filter(sessions::room_id.eq(room_id as i64))
+    .order_by(sessions::start_time.desc())
+    .limit(query.limit.get() as i64)
+    .get_results(conn)
+    .await
+    .context_into_app("Failed to query sessions")?;
+  Ok(Resp::new_success(sessions))
+}
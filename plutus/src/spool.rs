@@ -0,0 +1,319 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+  path::{Path, PathBuf},
+};
+
+use ahash::AHashMap;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::models::NewLog;
+
+const SPOOL_EXT: &str = "ndjson";
+
+/// A line of a spool file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Spooled<L = NewLog> {
+  #[serde(flatten)]
+  pub log: L,
+  /// Imported from an archive, replayed without tracking sessions
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub imported: bool,
+}
+
+/// Logs read from a spool file by [`Spool::read`]
+pub struct SpoolChunk {
+  pub logs: Vec<Spooled>,
+  /// Byte offset after each log
+  pub ends: Vec<u64>,
+  /// Byte offset after the chunk, malformed lines included
+  pub end: u64,
+}
+
+/// Append-only files buffering logs while the database is unavailable,
+/// one NDJSON file per room, e.g. `spool/1234.ndjson`.
+pub struct Spool {
+  dir: PathBuf,
+  pending: bool,
+}
+
+impl Spool {
+  /// Creates the directory if needed, files left by a previous run are replayed as well.
+  pub fn open<P: AsRef<Path>>(dir: P) -> anyhow::Result<Spool> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(&dir)
+      .with_context(|| format!("Failed to create spool dir `{}`", dir.to_string_lossy()))?;
+    let mut spool = Spool {
+      dir,
+      pending: false,
+    };
+    spool.pending = !spool.files()?.is_empty();
+    Ok(spool)
+  }
+
+  /// Whether any log is waiting to be replayed
+  pub fn is_pending(&self) -> bool {
+    self.pending
+  }
+
+  pub fn append<'a>(
+    &mut self,
+    logs: impl IntoIterator<Item = Spooled<&'a NewLog>>,
+  ) -> anyhow::Result<()> {
+    let mut rooms: AHashMap<i64, Vec<Spooled<&NewLog>>> = AHashMap::new();
+    for spooled in logs {
+      rooms.entry(spooled.log.room_id).or_default().push(spooled);
+    }
+    for (room_id, logs) in rooms {
+      let path = self.path(room_id);
+      let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open spool file `{}`", path.to_string_lossy()))?;
+      // A line partially written before a crash is ended, so the next log is not glued to it
+      if !ends_with_newline(&mut file)? {
+        file
+          .write_all(b"\n")
+          .with_context(|| format!("Failed to end last line of `{}`", path.to_string_lossy()))?;
+      }
+      write_logs(file, logs).with_context(|| {
+        format!(
+          "Failed to append to spool file `{}`",
+          path.to_string_lossy()
+        )
+      })?;
+      self.pending = true;
+    }
+    Ok(())
+  }
+
+  /// Spool files sorted by room id
+  pub fn files(&self) -> anyhow::Result<Vec<(i64, PathBuf)>> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(&self.dir)
+      .with_context(|| format!("Failed to list spool dir `{}`", self.dir.to_string_lossy()))?;
+    for entry in entries {
+      let path = entry.context("Failed to read spool dir entry")?.path();
+      if path.extension().is_none_or(|ext| ext != SPOOL_EXT) {
+        continue;
+      }
+      let Some(room_id) = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.parse::<i64>().ok())
+      else {
+        continue;
+      };
+      files.push((room_id, path));
+    }
+    files.sort_unstable_by_key(|(room_id, _)| *room_id);
+    Ok(files)
+  }
+
+  /// Reads at most `limit` logs in order, starting at byte `offset` of the file.
+  /// Malformed lines, e.g. a partially written last line, are skipped.
+  pub fn read(&self, path: &Path, offset: u64, limit: usize) -> anyhow::Result<SpoolChunk> {
+    let mut file = File::open(path)
+      .with_context(|| format!("Failed to open spool file `{}`", path.to_string_lossy()))?;
+    file
+      .seek(SeekFrom::Start(offset))
+      .with_context(|| format!("Failed to seek `{}`", path.to_string_lossy()))?;
+    let mut reader = BufReader::new(file);
+    let mut chunk = SpoolChunk {
+      logs: Vec::new(),
+      ends: Vec::new(),
+      end: offset,
+    };
+    let mut line = Vec::new();
+    while chunk.logs.len() < limit {
+      line.clear();
+      let read = reader
+        .read_until(b'\n', &mut line)
+        .with_context(|| format!("Failed to read `{}`", path.to_string_lossy()))?;
+      if read == 0 {
+        break;
+      }
+      chunk.end += read as u64;
+      if line.trim_ascii().is_empty() {
+        continue;
+      }
+      match serde_json::from_slice::<Spooled>(&line) {
+        Ok(log) => {
+          chunk.logs.push(log);
+          chunk.ends.push(chunk.end);
+        },
+        Err(err) => log::error!(
+          "Skipped malformed line at byte {} of `{}`: {err}",
+          chunk.end - read as u64,
+          path.to_string_lossy()
+        ),
+      }
+    }
+    Ok(chunk)
+  }
+
+  /// Drops the first `offset` bytes of the file, which are replayed, or removes it if nothing is left.
+  pub fn consume(&mut self, path: &Path, offset: u64) -> anyhow::Result<()> {
+    let mut file = File::open(path)
+      .with_context(|| format!("Failed to open spool file `{}`", path.to_string_lossy()))?;
+    let len = file
+      .metadata()
+      .with_context(|| format!("Failed to stat `{}`", path.to_string_lossy()))?
+      .len();
+    if offset >= len {
+      fs::remove_file(path)
+        .with_context(|| format!("Failed to remove spool file `{}`", path.to_string_lossy()))?;
+    } else if offset > 0 {
+      let tmp = path.with_extension("tmp");
+      let mut tmp_file = File::create(&tmp)
+        .with_context(|| format!("Failed to create `{}`", tmp.to_string_lossy()))?;
+      file.seek(SeekFrom::Start(offset))?;
+      io::copy(&mut file, &mut tmp_file)
+        .and_then(|_| tmp_file.sync_data())
+        .with_context(|| format!("Failed to write `{}`", tmp.to_string_lossy()))?;
+      fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace `{}`", path.to_string_lossy()))?;
+    }
+    self.pending = !self.files()?.is_empty();
+    Ok(())
+  }
+
+  fn path(&self, room_id: i64) -> PathBuf {
+    self.dir.join(format!("{room_id}.{SPOOL_EXT}"))
+  }
+}
+
+/// Whether the file is empty or its last byte is a line feed
+fn ends_with_newline(file: &mut File) -> anyhow::Result<bool> {
+  let len = file.metadata().context("Failed to stat spool file")?.len();
+  if len == 0 {
+    return Ok(true);
+  }
+  let mut last = [0u8];
+  file
+    .seek(SeekFrom::Start(len - 1))
+    .and_then(|_| file.read_exact(&mut last))
+    .context("Failed to read spool file")?;
+  Ok(last[0] == b'\n')
+}
+
+fn write_logs<'a>(
+  file: File,
+  logs: impl IntoIterator<Item = Spooled<&'a NewLog>>,
+) -> anyhow::Result<()> {
+  let mut writer = BufWriter::new(file);
+  for spooled in logs {
+    serde_json::to_writer(&mut writer, &spooled)?;
+    writer.write_all(b"\n")?;
+  }
+  let file = writer.into_inner().map_err(|err| err.into_error())?;
+  file.sync_data()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{TimeZone, Utc};
+  use serde_json::json;
+
+  use super::*;
+
+  fn spool(name: &str) -> Spool {
+    let dir = std::env::temp_dir().join(format!("plutus-spool-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    Spool::open(dir).unwrap()
+  }
+
+  fn log(room_id: i64, secs: i64) -> NewLog {
+    NewLog {
+      room_id,
+      command: "DANMU_MSG".to_string(),
+      raw_json: json!({ "cmd": "DANMU_MSG", "secs": secs }),
+      time: Utc.timestamp_opt(1700000000 + secs, 0).unwrap(),
+      related_uid: Some(secs),
+    }
+  }
+
+  fn append(spool: &mut Spool, logs: &[NewLog], imported: bool) {
+    spool
+      .append(logs.iter().map(|log| Spooled { log, imported }))
+      .unwrap();
+  }
+
+  fn secs(chunk: &SpoolChunk) -> Vec<i64> {
+    chunk
+      .logs
+      .iter()
+      .map(|spooled| spooled.log.related_uid.unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn append_read_consume() {
+    let mut spool = spool("round-trip");
+    assert!(!spool.is_pending());
+    append(&mut spool, &[log(1, 0), log(2, 1), log(1, 2)], false);
+    append(&mut spool, &[log(1, 3)], true);
+    assert!(spool.is_pending());
+
+    let files = spool.files().unwrap();
+    let rooms: Vec<_> = files.iter().map(|(room_id, _)| *room_id).collect();
+    assert_eq!(rooms, [1, 2]);
+    let path = &files[0].1;
+
+    let chunk = spool.read(path, 0, 2).unwrap();
+    assert_eq!(secs(&chunk), [0, 2]);
+    assert_eq!(chunk.logs[0].log.raw_json, log(1, 0).raw_json);
+    assert_eq!(chunk.logs[0].log.time, log(1, 0).time);
+    assert!(!chunk.logs[0].imported);
+    assert_eq!(chunk.end, chunk.ends[1]);
+
+    // The rest is kept after consuming the chunk
+    spool.consume(path, chunk.end).unwrap();
+    let chunk = spool.read(path, 0, 10).unwrap();
+    assert_eq!(secs(&chunk), [3]);
+    assert!(chunk.logs[0].imported);
+    assert_eq!(chunk.end, fs::metadata(path).unwrap().len());
+
+    spool.consume(path, chunk.end).unwrap();
+    assert!(!path.exists());
+    assert!(spool.is_pending());
+    let path = &files[1].1;
+    spool.consume(path, u64::MAX).unwrap();
+    assert!(!spool.is_pending());
+  }
+
+  #[test]
+  fn truncated_last_line() {
+    let mut spool = spool("truncated");
+    append(&mut spool, &[log(1, 0), log(1, 1)], false);
+    let (_, path) = spool.files().unwrap().remove(0);
+    // Crashed while writing the second line
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_len(len - 10)
+      .unwrap();
+
+    let chunk = spool.read(&path, 0, 10).unwrap();
+    assert_eq!(secs(&chunk), [0]);
+    assert_eq!(chunk.end, len - 10);
+
+    // Appended after the partial line instead of onto it
+    append(&mut spool, &[log(1, 2), log(1, 3)], false);
+    let chunk = spool.read(&path, 0, 10).unwrap();
+    assert_eq!(secs(&chunk), [0, 2, 3]);
+    assert_eq!(chunk.end, fs::metadata(&path).unwrap().len());
+
+    // Resumed from an offset
+    let rest = spool.read(&path, chunk.ends[0], 1).unwrap();
+    assert_eq!(secs(&rest), [2]);
+    spool.consume(&path, rest.end).unwrap();
+    assert_eq!(secs(&spool.read(&path, 0, 10).unwrap()), [3]);
+  }
+}
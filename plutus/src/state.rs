@@ -1,15 +1,17 @@
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
-use anyhow::{anyhow, Context};
-use diesel::{migration::MigrationVersion, Connection};
+use anyhow::Context;
 
-use crate::{config::Config, error::*, resp::AppCode};
-use diesel_async::{
-  async_connection_wrapper::AsyncConnectionWrapper,
-  pooled_connection::{AsyncDieselConnectionManager, PoolableConnection, RecyclingMethod},
-  AsyncPgConnection,
+use crate::{
+  app_err,
+  config::Config,
+  error::*,
+  models::NewLog,
+  resp::AppCode,
+  store::{Database, LogStore},
 };
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use tokio::sync::broadcast;
 
 pub type AsyncPool = bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>;
 pub type AsyncPoolConnection<'a> =
@@ -18,62 +20,58 @@ pub type AsyncPoolConnection<'a> =
 #[derive(Clone, Debug)]
 pub struct State {
   pub config: Arc<Config>,
-  db_pool: AsyncPool,
+  /// Only with PostgreSQL, sessions, rooms and stats are not supported by other stores
+  db_pool: Option<AsyncPool>,
+  store: Arc<dyn LogStore>,
+  feed: broadcast::Sender<Arc<NewLog>>,
 }
 
 impl State {
   pub async fn init() -> anyhow::Result<Self> {
     let config_path =
       std::env::var("PLUTUS_CONFIG").unwrap_or_else(|_err| "plutus-config.toml".to_string());
-    let config = Config::load(config_path)
-      .context("Failed to load plutus config")
-      .map(Arc::new)?;
+    let config = Config::load(config_path).context("Failed to load plutus config")?;
 
-    let db_pool = bb8::Pool::builder()
-      .connection_timeout(Duration::from_secs(3))
-      .build(AsyncDieselConnectionManager::<AsyncPgConnection>::new(
-        &config.database_url,
-      ))
+    let database = Database::connect(&config.database_url)
       .await
-      .context("Failed to create bb8 pool.")?;
-    {
-      let mut db_con = db_pool
-        .get()
-        .await
-        .context("Failed to get db connection from pool")?;
-      db_con
-        .ping(&RecyclingMethod::Verified)
-        .await
-        .context("Failed to ping database")?;
+      .context("Failed to connect to database")?;
+    let store = database.log_store();
+    let db_pool = match database {
+      Database::Postgres(pool) => Some(pool),
+      Database::Sqlite(_) | Database::Memory(_) => None,
+    };
 
-      pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
-
-      let database_url = config.database_url.clone();
-      tokio::task::spawn_blocking(move || {
-        let mut async_wrapper: AsyncConnectionWrapper<AsyncPgConnection> =
-          AsyncConnectionWrapper::establish(&database_url)
-            .context("Failed to setup migration connection")?;
+    Ok(State::new(config, db_pool, store))
+  }
 
-        log::info!("Running database migrations...");
-        let versions: Vec<MigrationVersion> = async_wrapper
-          .run_pending_migrations(MIGRATIONS)
-          .map_err(|err| anyhow!(err))
-          .context("Failed to run migrations")?;
-        if let Some(last) = versions.last() {
-          log::info!("Current migration version: {last}");
-        }
-        anyhow::Ok(())
-      })
-      .await
-      .context("migration job failed")??;
+  pub fn new(config: Config, db_pool: Option<AsyncPool>, store: Arc<dyn LogStore>) -> State {
+    let (feed, _) = broadcast::channel(config.feed_buffer.max(1));
+    State {
+      config: Arc::new(config),
+      db_pool,
+      store,
+      feed,
     }
+  }
 
-    Ok(State { config, db_pool })
+  pub fn store(&self) -> &dyn LogStore {
+    &*self.store
   }
 
-  pub async fn db_con(&self) -> AppResult<AsyncPoolConnection<'_>> {
+  pub fn is_postgres(&self) -> bool {
+    self.db_pool.is_some()
+  }
+
+  fn db_pool(&self) -> AppResult<&AsyncPool> {
     self
       .db_pool
+      .as_ref()
+      .ok_or_else(|| app_err!(AppCode::DATABASE_ERROR, "Only available with PostgreSQL"))
+  }
+
+  pub async fn db_con(&self) -> AppResult<AsyncPoolConnection<'_>> {
+    self
+      .db_pool()?
       .get()
       .await
       .context("Failed to get pooled database connection")
@@ -83,11 +81,22 @@ impl State {
 
   pub async fn db_con_owned(&self) -> AppResult<AsyncPoolConnection<'static>> {
     self
-      .db_pool
+      .db_pool()?
       .get_owned()
       .await
       .context("Failed to get pooled database connection")
       .with_app_error(AppCode::DATABASE_ERROR)
       .into_app_result()
   }
+
+  /// Publishes a collected log to live feed subscribers, if any
+  pub fn publish(&self, log: &NewLog) {
+    if self.feed.receiver_count() != 0 {
+      let _ = self.feed.send(Arc::new(log.clone()));
+    }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<Arc<NewLog>> {
+    self.feed.subscribe()
+  }
 }
@@ -0,0 +1,321 @@
+use axum::Json;
+use chrono::{DateTime, Utc};
+use diesel::{
+  dsl::sql,
+  result::Error as DieselError,
+  sql_query,
+  sql_types::{BigInt, Bool, Nullable, Text, Timestamptz, Varchar},
+  QueryableByName,
+};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  app_err,
+  error::{AppResp, AppResult, IntoAppResult},
+  global_state,
+  resp::{AppCode, Resp},
+  routes::{check_text_search, QueryBody, TimeRange},
+  sessions,
+  state::AsyncPoolConnection,
+  store::{self, CommandCount, LogFilter},
+};
+
+/// 1000 gold coins = 1 CNY
+pub const GOLD_PER_CNY: i64 = 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct RevenueQuery {
+  pub room_id: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub time_range: Option<TimeRange>,
+  /// Only revenue during the session, see `/rooms/{id}/sessions`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub session: Option<i64>,
+  #[serde(default)]
+  pub bucket: Bucket,
+  #[serde(default)]
+  pub unit: RevenueUnit,
+  /// Time zone used to truncate `hour` and `day` buckets, e.g. "Asia/Shanghai"
+  #[serde(default = "RevenueQuery::default_timezone")]
+  pub timezone: String,
+}
+
+impl RevenueQuery {
+  fn default_timezone() -> String {
+    "UTC".to_string()
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+  Hour,
+  #[default]
+  Day,
+  /// Live session, bucket start is the session start
+  Session,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RevenueUnit {
+  #[default]
+  Cny,
+  Gold,
+}
+
+impl RevenueUnit {
+  #[inline]
+  fn convert(self, gold: i64) -> f64 {
+    match self {
+      RevenueUnit::Cny => gold as f64 / GOLD_PER_CNY as f64,
+      RevenueUnit::Gold => gold as f64,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevenueStats {
+  pub room_id: u64,
+  pub unit: RevenueUnit,
+  pub buckets: Vec<RevenueBucket>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RevenueBucket {
+  pub start: DateTime<Utc>,
+  /// Paid gifts (`SEND_GIFT` with gold coins)
+  pub gift: RevenueItem,
+  /// `GUARD_BUY`
+  pub guard: RevenueItem,
+  /// `SUPER_CHAT_MESSAGE`
+  pub super_chat: RevenueItem,
+  /// `USER_TOAST_MSG`, always sent along with `GUARD_BUY`, so it is not part of `total`
+  pub user_toast: RevenueItem,
+  pub total: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RevenueItem {
+  pub count: u64,
+  pub amount: f64,
+}
+
+#[derive(QueryableByName, Debug)]
+struct RevenueRow {
+  #[diesel(sql_type = Timestamptz)]
+  bucket: DateTime<Utc>,
+  #[diesel(sql_type = Varchar)]
+  command: String,
+  #[diesel(sql_type = BigInt)]
+  count: i64,
+  #[diesel(sql_type = BigInt)]
+  gold: i64,
+}
+
+/// Every amount is normalized to gold coins in SQL, SuperChat prices are in CNY.
+const REVENUE_SQL: &str = r#"
+SELECT bucket, command, COUNT(*) AS count, COALESCE(SUM(gold), 0)::BIGINT AS gold
+FROM (
+  SELECT {bucket} AS bucket,
+    logs.command,
+    CASE logs.command
+      WHEN 'SEND_GIFT' THEN (logs.raw_json->'data'->>'total_coin')::BIGINT
+      WHEN 'GUARD_BUY' THEN (logs.raw_json->'data'->>'price')::BIGINT * (logs.raw_json->'data'->>'num')::BIGINT
+      WHEN 'USER_TOAST_MSG' THEN (logs.raw_json->'data'->>'price')::BIGINT * (logs.raw_json->'data'->>'num')::BIGINT
+      WHEN 'SUPER_CHAT_MESSAGE' THEN (logs.raw_json->'data'->>'price')::BIGINT * 1000
+    END AS gold
+  FROM logs
+  WHERE logs.room_id = $1
+    AND logs.command IN ('SEND_GIFT', 'GUARD_BUY', 'USER_TOAST_MSG', 'SUPER_CHAT_MESSAGE')
+    AND (logs.command <> 'SEND_GIFT' OR logs.raw_json->'data'->>'coin_type' = 'gold')
+    AND ($2::TIMESTAMPTZ IS NULL OR logs."time" >= $2)
+    AND ($3::TIMESTAMPTZ IS NULL OR logs."time" <= $3)
+) AS revenue
+WHERE bucket IS NOT NULL
+GROUP BY bucket, command
+ORDER BY bucket
+"#;
+
+const HOUR_BUCKET: &str = r#"date_trunc('hour', logs."time", $4)"#;
+const DAY_BUCKET: &str = r#"date_trunc('day', logs."time", $4)"#;
+const SESSION_BUCKET: &str = r#"(
+    SELECT MAX(sessions.start_time) FROM sessions
+    WHERE sessions.room_id = logs.room_id AND sessions.start_time <= logs."time"
+      AND (sessions.end_time IS NULL OR sessions.end_time >= logs."time")
+  )"#;
+
+/// Number of logs per command, filtered like `/list`
+pub async fn commands(Json(body): Json<QueryBody>) -> AppResp<Vec<CommandCount>> {
+  let session_range = match body.session {
+    Some(session_id) => {
+      let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+      Some(sessions::time_range(conn, body.room_id, session_id).await?)
+    },
+    None => None,
+  };
+  let filter = LogFilter::new(&body, session_range.as_ref());
+  check_text_search(global_state(), &filter).await?;
+  let counts = global_state()
+    .store()
+    .stats(&filter)
+    .await
+    .context_into_app("Failed to count commands")?;
+  Ok(Resp::new_success(counts))
+}
+
+/// The time zone is checked by PostgreSQL first, so an unknown one is reported as an invalid argument
+async fn check_timezone(conn: &mut AsyncPoolConnection<'_>, timezone: &str) -> AppResult<()> {
+  let result = diesel::select(
+    sql::<Bool>("(now() AT TIME ZONE ")
+      .bind::<Text, _>(timezone)
+      .sql(") IS NOT NULL"),
+  )
+  .get_result::<bool>(conn)
+  .await;
+  match result {
+    Ok(_) => Ok(()),
+    Err(err) if store::is_unavailable(&err) => {
+      Err(err).context_into_app("Failed to check timezone")
+    },
+    Err(DieselError::DatabaseError(_, info)) => Err(app_err!(
+      AppCode::INVALID_ARGUMENTS,
+      "Invalid timezone: {}",
+      info.message()
+    )),
+    Err(err) => Err(err).context_into_app("Failed to check timezone"),
+  }
+}
+
+pub async fn revenue(Json(body): Json<RevenueQuery>) -> AppResp<RevenueStats> {
+  let conn: &mut AsyncPoolConnection = &mut global_state().db_con().await?;
+  check_timezone(conn, &body.timezone).await?;
+
+  let bucket = match body.bucket {
+    Bucket::Hour => HOUR_BUCKET,
+    Bucket::Day => DAY_BUCKET,
+    Bucket::Session => SESSION_BUCKET,
+  };
+  let (mut start, mut end) = body
+    .time_range
+    .map(|range| (range.start, range.end))
+    .unwrap_or_default();
+  if let Some(session_id) = body.session {
+    let session = sessions::time_range(conn, body.room_id, session_id).await?;
+    start = start.max(session.start);
+    end = match (end, session.end) {
+      (Some(end), Some(session_end)) => Some(end.min(session_end)),
+      (end, session_end) => end.or(session_end),
+    };
+  }
+
+  let rows: Vec<RevenueRow> = sql_query(REVENUE_SQL.replace("{bucket}", bucket))
+    .bind::<BigInt, _>(body.room_id as i64)
+    .bind::<Nullable<Timestamptz>, _>(start)
+    .bind::<Nullable<Timestamptz>, _>(end)
+    .bind::<Text, _>(&body.timezone)
+    .load(conn)
+    .await
+    .context_into_app("Failed to aggregate revenue")?;
+
+  Ok(Resp::new_success(RevenueStats {
+    room_id: body.room_id,
+    unit: body.unit,
+    buckets: fold_buckets(rows, body.unit),
+  }))
+}
+
+fn fold_buckets(rows: Vec<RevenueRow>, unit: RevenueUnit) -> Vec<RevenueBucket> {
+  let mut buckets: Vec<RevenueBucket> = Vec::new();
+  for row in rows {
+    let bucket = match buckets.last_mut() {
+      Some(last) if last.start == row.bucket => last,
+      _ => {
+        buckets.push(RevenueBucket {
+          start: row.bucket,
+          ..Default::default()
+        });
+        buckets.last_mut().unwrap()
+      },
+    };
+    let item = match row.command.as_str() {
+      "SEND_GIFT" => &mut bucket.gift,
+      "GUARD_BUY" => &mut bucket.guard,
+      "SUPER_CHAT_MESSAGE" => &mut bucket.super_chat,
+      "USER_TOAST_MSG" => &mut bucket.user_toast,
+      _ => continue,
+    };
+    item.count += row.count as u64;
+    item.amount += unit.convert(row.gold);
+  }
+  for bucket in buckets.iter_mut() {
+    bucket.total = bucket.gift.amount + bucket.guard.amount + bucket.super_chat.amount;
+  }
+  buckets
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  fn row(hour: u32, command: &str, count: i64, gold: i64) -> RevenueRow {
+    RevenueRow {
+      bucket: Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap(),
+      command: command.to_string(),
+      count,
+      gold,
+    }
+  }
+
+  #[test]
+  fn fold_no_rows() {
+    assert!(fold_buckets(Vec::new(), RevenueUnit::Cny).is_empty());
+  }
+
+  #[test]
+  fn fold_rows_by_bucket() {
+    let rows = vec![
+      row(0, "SEND_GIFT", 2, 1500),
+      row(0, "SUPER_CHAT_MESSAGE", 1, 30000),
+      // The next bucket starts right after, hours without revenue are not filled
+      row(1, "GUARD_BUY", 1, 198000),
+      row(1, "USER_TOAST_MSG", 1, 198000),
+      row(3, "SEND_GIFT", 1, 100),
+    ];
+    let buckets = fold_buckets(rows, RevenueUnit::Cny);
+    let starts: Vec<_> = buckets.iter().map(|bucket| bucket.start).collect();
+    assert_eq!(
+      starts,
+      [0, 1, 3].map(|hour| Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap())
+    );
+
+    assert_eq!(buckets[0].gift.count, 2);
+    assert_eq!(buckets[0].gift.amount, 1.5);
+    assert_eq!(buckets[0].super_chat.amount, 30.0);
+    assert_eq!(buckets[0].guard.count, 0);
+    assert_eq!(buckets[0].total, 31.5);
+
+    // `USER_TOAST_MSG` duplicates `GUARD_BUY`
+    assert_eq!(buckets[1].user_toast.amount, 198.0);
+    assert_eq!(buckets[1].total, 198.0);
+    assert_eq!(buckets[2].total, 0.1);
+  }
+
+  #[test]
+  fn fold_bucket_of_unknown_command() {
+    let buckets = fold_buckets(vec![row(0, "LIVE", 1, 0)], RevenueUnit::Gold);
+    assert_eq!(buckets.len(), 1);
+    assert_eq!(buckets[0].gift.count, 0);
+    assert_eq!(buckets[0].total, 0.0);
+  }
+
+  #[test]
+  fn fold_in_gold() {
+    let buckets = fold_buckets(vec![row(0, "SEND_GIFT", 1, 1500)], RevenueUnit::Gold);
+    assert_eq!(buckets[0].gift.amount, 1500.0);
+    assert_eq!(buckets[0].total, 1500.0);
+  }
+}
@@ -0,0 +1,872 @@
+use std::{
+  fmt::Debug,
+  sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, RwLock,
+  },
+  time::Duration,
+};
+
+use ahash::AHashMap;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use diesel::{
+  connection::SimpleConnection,
+  dsl::{count_star, sql},
+  migration::MigrationVersion,
+  pg::Pg,
+  result::{ConnectionError, DatabaseErrorKind, Error as DieselError},
+  sql_types::{BigInt, Bool, Nullable, Text, Timestamptz, TimestamptzSqlite},
+  sqlite::SqliteConnection,
+  BoolExpressionMethods, Connection, ExpressionMethods, IntoSql, PgTextExpressionMethods, QueryDsl,
+};
+use diesel_async::{
+  async_connection_wrapper::AsyncConnectionWrapper,
+  pooled_connection::{
+    AsyncDieselConnectionManager, ManagerConfig, PoolableConnection, RecyclingMethod,
+  },
+  scoped_futures::ScopedFutureExt,
+  sync_connection_wrapper::SyncConnectionWrapper,
+  AsyncConnection, AsyncPgConnection, RunQueryDsl, SimpleAsyncConnection,
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  models::{Log, NewLog, NewSqliteLog, SqliteLog},
+  routes::{QueryBody, TimeRange},
+  schema::{danmaku, logs, super_chats},
+  schema_sqlite::logs as sqlite_logs,
+  state::AsyncPool,
+  typed::{self, TypedRow},
+};
+
+pub type SqlitePool =
+  bb8::Pool<AsyncDieselConnectionManager<SyncConnectionWrapper<SqliteConnection>>>;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+/// Storage of collected logs, implemented by PostgreSQL, SQLite and memory.
+#[async_trait]
+pub trait LogStore: Debug + Send + Sync {
+  /// Inserts logs along with their typed rows, stores without typed tables ignore them
+  async fn insert(&self, logs: Vec<(&NewLog, Option<&TypedRow>)>) -> Result<(), StoreError>;
+
+  async fn count(&self, filter: &LogFilter) -> anyhow::Result<i64>;
+
+  /// Logs ordered by `(time, id)`, starting after `after` if given
+  async fn list(
+    &self,
+    filter: &LogFilter,
+    after: Option<&LogKey>,
+    offset: i64,
+    limit: i64,
+  ) -> anyhow::Result<Vec<Log>>;
+
+  /// Number of logs per command, in descending order
+  async fn stats(&self, filter: &LogFilter) -> anyhow::Result<Vec<CommandCount>>;
+
+  /// Logs of the room saved at exactly these times, to find duplicates of imported logs
+  async fn saved_at(&self, room_id: i64, times: &[DateTime<Utc>]) -> anyhow::Result<Vec<Log>>;
+
+  /// Deletes at most `limit` logs of the scope before `before` in a batch,
+  /// returns 0 once nothing is left to prune
+  async fn prune(
+    &self,
+    scope: PruneScope<'_>,
+    before: DateTime<Utc>,
+    limit: i64,
+  ) -> anyhow::Result<usize>;
+}
+
+/// Commands pruned by a retention rule
+#[derive(Debug, Clone, Copy)]
+pub enum PruneScope<'a> {
+  Command(&'a str),
+  /// Every command except these, which have rules of their own
+  Except(&'a [String]),
+}
+
+impl PruneScope<'_> {
+  pub fn contains(&self, command: &str) -> bool {
+    match *self {
+      PruneScope::Command(cmd) => cmd == command,
+      PruneScope::Except(commands) => !commands.iter().any(|cmd| cmd == command),
+    }
+  }
+}
+
+pub enum StoreError {
+  /// Connection failures, the logs should be retried later
+  Unavailable(anyhow::Error),
+  /// The statement is rejected by the database
+  Rejected(anyhow::Error),
+}
+
+impl From<DieselError> for StoreError {
+  fn from(err: DieselError) -> Self {
+    if is_unavailable(&err) {
+      StoreError::Unavailable(anyhow!(err))
+    } else {
+      StoreError::Rejected(anyhow!(err))
+    }
+  }
+}
+
+/// Messages of connection and operator intervention errors (SQLSTATE 53300, 57P01-57P05 and 57014)
+/// and SQLite lock errors, since diesel doesn't expose SQLSTATE. Errors closing the connection are caught by `is_broken` as well.
+const UNAVAILABLE_MESSAGES: &[&str] = &[
+  "terminating connection",
+  "the database system is",
+  "canceling statement due to",
+  "sorry, too many clients",
+  "remaining connection slots",
+  // SQLITE_BUSY and SQLITE_LOCKED, another process is writing
+  "database is locked",
+  "database table is locked",
+];
+
+/// Waits this long for the write lock of SQLite held by another process, e.g. `plutus import`
+const SQLITE_BUSY_TIMEOUT_MS: u32 = 5000;
+/// SQLite allows at most 32766 bound variables in a statement, `logs` has 5 columns
+const SQLITE_MAX_ROWS: usize = 32766 / 5;
+
+/// Whether the error is a connection failure or operator intervention rather than a rejected statement
+pub fn is_unavailable(err: &DieselError) -> bool {
+  match err {
+    DieselError::DatabaseError(
+      DatabaseErrorKind::UnableToSendCommand | DatabaseErrorKind::ClosedConnection,
+      _,
+    ) => true,
+    DieselError::DatabaseError(DatabaseErrorKind::Unknown, info) => UNAVAILABLE_MESSAGES
+      .iter()
+      .any(|message| info.message().starts_with(message)),
+    _ => false,
+  }
+}
+
+/// Filters of [`QueryBody`], time ranges are merged into one
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+  pub room_id: i64,
+  pub commands: Vec<String>,
+  pub uid: Option<i64>,
+  pub start: Option<DateTime<Utc>>,
+  pub end: Option<DateTime<Utc>>,
+  pub keyword: Option<String>,
+  pub regex: Option<String>,
+}
+
+impl LogFilter {
+  pub fn new(body: &QueryBody, session_range: Option<&TimeRange>) -> LogFilter {
+    let mut filter = LogFilter {
+      room_id: body.room_id as i64,
+      commands: body.commands.clone(),
+      uid: body.uid.map(|uid| uid as i64),
+      start: None,
+      end: None,
+      keyword: body.keyword.clone().filter(|keyword| !keyword.is_empty()),
+      regex: body.regex.clone().filter(|regex| !regex.is_empty()),
+    };
+    for range in body.time_range.iter().chain(session_range) {
+      filter.start = filter.start.max(range.start);
+      filter.end = match (filter.end, range.end) {
+        (Some(end), Some(range_end)) => Some(end.min(range_end)),
+        (end, range_end) => end.or(range_end),
+      };
+    }
+    filter
+  }
+
+  /// Keyword and regex are only supported by [`PgStore`], other stores ignore them
+  pub fn has_text_search(&self) -> bool {
+    self.keyword.is_some() || self.regex.is_some()
+  }
+
+  pub fn matches(&self, log: &Log) -> bool {
+    log.room_id == self.room_id
+      && (self.commands.is_empty() || self.commands.contains(&log.command))
+      && self.uid.is_none_or(|uid| Some(uid) == log.related_uid)
+      && self.start.is_none_or(|start| log.time >= start)
+      && self.end.is_none_or(|end| log.time <= end)
+  }
+}
+
+/// Position of a log in `(time, id)` order, for keyset pagination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogKey {
+  pub time: DateTime<Utc>,
+  pub id: i64,
+}
+
+impl LogKey {
+  pub fn of(log: &Log) -> LogKey {
+    LogKey {
+      time: log.time,
+      id: log.id,
+    }
+  }
+
+  /// Encodes as `{secs}_{nanos}_{id}`, clients should treat it as opaque
+  pub fn encode(&self) -> String {
+    format!(
+      "{}_{}_{}",
+      self.time.timestamp(),
+      self.time.timestamp_subsec_nanos(),
+      self.id
+    )
+  }
+
+  pub fn decode(cursor: &str) -> Option<LogKey> {
+    let mut parts = cursor.splitn(3, '_');
+    let secs = parts.next()?.parse().ok()?;
+    let nanos = parts.next()?.parse().ok()?;
+    let id = parts.next()?.parse().ok()?;
+    Some(LogKey {
+      time: DateTime::from_timestamp(secs, nanos)?,
+      id,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandCount {
+  pub command: String,
+  pub count: i64,
+}
+
+impl CommandCount {
+  fn sort(counts: &mut [CommandCount]) {
+    counts.sort_unstable_by(|a, b| {
+      b.count
+        .cmp(&a.count)
+        .then_with(|| a.command.cmp(&b.command))
+    });
+  }
+}
+
+/// Applies a [`LogFilter`] to a boxed query on `logs` of any backend
+macro_rules! filter_logs {
+  ($query:expr, $logs:ident, $filter:expr) => {{
+    let mut query = $query.filter($logs::room_id.eq($filter.room_id));
+    if !$filter.commands.is_empty() {
+      query = query.filter($logs::command.eq_any(&$filter.commands));
+    }
+    if let Some(uid) = $filter.uid {
+      query = query.filter($logs::related_uid.eq(uid));
+    }
+    if let Some(start) = $filter.start {
+      query = query.filter($logs::time.ge(start));
+    }
+    if let Some(end) = $filter.end {
+      query = query.filter($logs::time.le(end));
+    }
+    query
+  }};
+}
+
+/// Continues a boxed query on `logs` after the [`LogKey`] in `(time, id)` order.
+/// The row value comparison lets the `(room_id, time, id)` index seek to the key.
+macro_rules! after_key {
+  ($query:expr, $after:expr, $time_type:ty) => {{
+    let mut query = $query;
+    if let Some(key) = $after {
+      query = query.filter(
+        sql::<Bool>(r#"("time", id) > ("#)
+          .bind::<$time_type, _>(key.time)
+          .sql(", ")
+          .bind::<BigInt, _>(key.id)
+          .sql(")"),
+      );
+    }
+    query
+  }};
+}
+
+diesel::infix_operator!(RegexMatch, " ~ ", backend: Pg);
+
+/// Applies keyword and regex of a [`LogFilter`] to a boxed query on `logs` of PostgreSQL,
+/// matched against typed tables by the trigram indexes.
+/// Logs saved before the typed tables existed are only found after `plutus reindex`.
+macro_rules! filter_content {
+  ($query:expr, $filter:expr) => {{
+    let mut query = $query;
+    if let Some(ref keyword) = $filter.keyword {
+      let pattern = format!("%{}%", escape_like(keyword));
+      query = query.filter(
+        logs::id
+          .eq_any(
+            danmaku::table
+              .filter(danmaku::room_id.eq($filter.room_id))
+              .filter(danmaku::content.ilike(pattern.clone()))
+              .select(danmaku::log_id),
+          )
+          .or(
+            logs::id.eq_any(
+              super_chats::table
+                .filter(super_chats::room_id.eq($filter.room_id))
+                .filter(super_chats::message.ilike(pattern))
+                .select(super_chats::log_id),
+            ),
+          ),
+      );
+    }
+    if let Some(ref regex) = $filter.regex {
+      query = query.filter(
+        logs::id
+          .eq_any(
+            danmaku::table
+              .filter(danmaku::room_id.eq($filter.room_id))
+              .filter(RegexMatch::new(
+                danmaku::content,
+                regex.as_str().into_sql::<Text>(),
+              ))
+              .select(danmaku::log_id),
+          )
+          .or(
+            logs::id.eq_any(
+              super_chats::table
+                .filter(super_chats::room_id.eq($filter.room_id))
+                .filter(RegexMatch::new(
+                  super_chats::message,
+                  regex.as_str().into_sql::<Nullable<Text>>(),
+                ))
+                .select(super_chats::log_id),
+            ),
+          ),
+      );
+    }
+    query
+  }};
+}
+
+/// Selects ids of at most `$limit` logs of the scope before `$before`
+macro_rules! prune_ids {
+  ($logs:ident, $scope:expr, $before:expr, $limit:expr) => {{
+    let query = $logs::table
+      .filter($logs::time.lt($before))
+      .select($logs::id)
+      .limit($limit)
+      .into_boxed();
+    match $scope {
+      PruneScope::Command(command) => query.filter($logs::command.eq(command)),
+      PruneScope::Except(commands) => query.filter($logs::command.ne_all(commands)),
+    }
+  }};
+}
+
+/// Escapes wildcards of LIKE, backslash is the default escape character of PostgreSQL
+fn escape_like(keyword: &str) -> String {
+  let mut escaped = String::with_capacity(keyword.len());
+  for char in keyword.chars() {
+    if matches!(char, '\\' | '%' | '_') {
+      escaped.push('\\');
+    }
+    escaped.push(char);
+  }
+  escaped
+}
+
+/// Database selected by the scheme of `database-url`, `sqlite://plutus.db` for SQLite,
+/// `memory:` for [`MemoryStore`], otherwise PostgreSQL.
+pub enum Database {
+  Postgres(AsyncPool),
+  Sqlite(SqlitePool),
+  Memory(Arc<MemoryStore>),
+}
+
+impl Database {
+  /// Connects to the database and runs pending migrations
+  pub async fn connect(database_url: &str) -> anyhow::Result<Database> {
+    if database_url == "memory:" {
+      log::warn!("Logs are kept in memory only, they are lost after exiting");
+      return Ok(Database::Memory(Arc::default()));
+    }
+    match database_url
+      .strip_prefix("sqlite://")
+      .or_else(|| database_url.strip_prefix("sqlite:"))
+    {
+      Some(path) => Self::connect_sqlite(path).await,
+      None => Self::connect_postgres(database_url).await,
+    }
+  }
+
+  async fn connect_postgres(database_url: &str) -> anyhow::Result<Database> {
+    let db_pool = bb8::Pool::builder()
+      .connection_timeout(Duration::from_secs(3))
+      .build(AsyncDieselConnectionManager::<AsyncPgConnection>::new(
+        database_url,
+      ))
+      .await
+      .context("Failed to create bb8 pool.")?;
+
+    let mut db_con = db_pool
+      .get()
+      .await
+      .context("Failed to get db connection from pool")?;
+    db_con
+      .ping(&RecyclingMethod::Verified)
+      .await
+      .context("Failed to ping database")?;
+    drop(db_con);
+
+    let database_url = database_url.to_string();
+    tokio::task::spawn_blocking(move || {
+      let mut async_wrapper: AsyncConnectionWrapper<AsyncPgConnection> =
+        Connection::establish(&database_url).context("Failed to setup migration connection")?;
+      run_migrations(&mut async_wrapper, MIGRATIONS)
+    })
+    .await
+    .context("migration job failed")??;
+
+    Ok(Database::Postgres(db_pool))
+  }
+
+  async fn connect_sqlite(path: &str) -> anyhow::Result<Database> {
+    let database_path = path.to_string();
+    tokio::task::spawn_blocking(move || {
+      let mut conn = SqliteConnection::establish(&database_path)
+        .with_context(|| format!("Failed to open SQLite database `{database_path}`"))?;
+      conn
+        .batch_execute("PRAGMA journal_mode = WAL")
+        .context("Failed to enable WAL")?;
+      conn
+        .batch_execute(&format!("PRAGMA busy_timeout = {SQLITE_BUSY_TIMEOUT_MS}"))
+        .context("Failed to set busy timeout")?;
+      run_migrations(&mut conn, SQLITE_MIGRATIONS)
+    })
+    .await
+    .context("migration job failed")??;
+
+    // `busy_timeout` is a setting of the connection, not of the database
+    let mut manager_config = ManagerConfig::default();
+    manager_config.custom_setup = Box::new(|url| {
+      async move {
+        let mut conn = SyncConnectionWrapper::<SqliteConnection>::establish(url).await?;
+        conn
+          .batch_execute(&format!("PRAGMA busy_timeout = {SQLITE_BUSY_TIMEOUT_MS}"))
+          .await
+          .map_err(ConnectionError::CouldntSetupConfiguration)?;
+        Ok(conn)
+      }
+      .boxed()
+    });
+    // SQLite allows only one writer at a time
+    let db_pool = bb8::Pool::builder()
+      .max_size(1)
+      .connection_timeout(Duration::from_secs(3))
+      .build(AsyncDieselConnectionManager::<
+        SyncConnectionWrapper<SqliteConnection>,
+      >::new_with_config(path, manager_config))
+      .await
+      .context("Failed to create bb8 pool.")?;
+    Ok(Database::Sqlite(db_pool))
+  }
+
+  pub fn log_store(&self) -> Arc<dyn LogStore> {
+    match self {
+      Database::Postgres(pool) => Arc::new(PgStore { pool: pool.clone() }),
+      Database::Sqlite(pool) => Arc::new(SqliteStore { pool: pool.clone() }),
+      Database::Memory(store) => Arc::clone(store) as Arc<dyn LogStore>,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct PgStore {
+  pool: AsyncPool,
+}
+
+#[async_trait]
+impl LogStore for PgStore {
+  async fn insert(&self, logs: Vec<(&NewLog, Option<&TypedRow>)>) -> Result<(), StoreError> {
+    let mut conn = self
+      .pool
+      .get()
+      .await
+      .map_err(|err| StoreError::Unavailable(anyhow!(err)))?;
+    conn
+      .transaction(|conn| {
+        async move {
+          let ids: Vec<i64> = diesel::insert_into(logs::table)
+            .values(logs.iter().map(|(log, _)| *log).collect::<Vec<_>>())
+            .returning(logs::id)
+            .get_results(conn)
+            .await?;
+          // Ids are returned in the order of the values
+          let rows: Vec<_> = ids
+            .into_iter()
+            .zip(&logs)
+            .filter_map(|(id, (log, typed))| typed.map(|typed| (id, log.room_id, log.time, typed)))
+            .collect();
+          typed::insert_or_skip(conn, &rows).await
+        }
+        .scope_boxed()
+      })
+      .await
+      .map_err(|err| {
+        // e.g. admin shutdown or a server crash during the query
+        if conn.is_broken() {
+          StoreError::Unavailable(anyhow!(err))
+        } else {
+          StoreError::from(err)
+        }
+      })
+  }
+
+  async fn count(&self, filter: &LogFilter) -> anyhow::Result<i64> {
+    let mut conn = self.pool.get().await?;
+    let query = filter_logs!(logs::table.into_boxed(), logs, filter);
+    let count = filter_content!(query, filter)
+      .count()
+      .get_result(&mut conn)
+      .await?;
+    Ok(count)
+  }
+
+  async fn list(
+    &self,
+    filter: &LogFilter,
+    after: Option<&LogKey>,
+    offset: i64,
+    limit: i64,
+  ) -> anyhow::Result<Vec<Log>> {
+    let mut conn = self.pool.get().await?;
+    let query = filter_logs!(logs::table.into_boxed(), logs, filter);
+    let query = filter_content!(query, filter);
+    let logs = after_key!(query, after, Timestamptz)
+      .limit(limit)
+      .offset(offset)
+      .order_by((logs::time, logs::id))
+      .get_results(&mut conn)
+      .await?;
+    Ok(logs)
+  }
+
+  async fn saved_at(&self, room_id: i64, times: &[DateTime<Utc>]) -> anyhow::Result<Vec<Log>> {
+    let mut conn = self.pool.get().await?;
+    let logs = logs::table
+      .filter(logs::room_id.eq(room_id))
+      .filter(logs::time.eq_any(times))
+      .get_results(&mut conn)
+      .await?;
+    Ok(logs)
+  }
+
+  async fn stats(&self, filter: &LogFilter) -> anyhow::Result<Vec<CommandCount>> {
+    let mut conn = self.pool.get().await?;
+    let query = filter_logs!(
+      logs::table
+        .group_by(logs::command)
+        .select((logs::command, count_star()))
+        .into_boxed(),
+      logs,
+      filter
+    );
+    let rows: Vec<(String, i64)> = filter_content!(query, filter).load(&mut conn).await?;
+    let mut counts: Vec<CommandCount> = rows
+      .into_iter()
+      .map(|(command, count)| CommandCount { command, count })
+      .collect();
+    CommandCount::sort(&mut counts);
+    Ok(counts)
+  }
+
+  async fn prune(
+    &self,
+    scope: PruneScope<'_>,
+    before: DateTime<Utc>,
+    limit: i64,
+  ) -> anyhow::Result<usize> {
+    let mut conn = self.pool.get().await?;
+    let ids = prune_ids!(logs, scope, before, limit);
+    let deleted = diesel::delete(
+      logs::table
+        .filter(logs::time.lt(before))
+        .filter(logs::id.eq_any(ids)),
+    )
+    .execute(&mut conn)
+    .await?;
+    // Typed rows have the time of their logs, so they are pruned by the same rule
+    let typed = typed::prune(&mut conn, scope, before, limit).await?;
+    Ok(deleted.max(typed))
+  }
+}
+
+#[derive(Debug)]
+pub struct SqliteStore {
+  pool: SqlitePool,
+}
+
+#[async_trait]
+impl LogStore for SqliteStore {
+  async fn insert(&self, logs: Vec<(&NewLog, Option<&TypedRow>)>) -> Result<(), StoreError> {
+    let mut conn = self
+      .pool
+      .get()
+      .await
+      .map_err(|err| StoreError::Unavailable(anyhow!(err)))?;
+    let logs: Vec<NewSqliteLog> = logs
+      .into_iter()
+      .map(|(log, _)| NewSqliteLog::from(log))
+      .collect();
+    // Batch insert of SQLite is only implemented for the sync connection
+    conn
+      .spawn_blocking(move |conn| {
+        conn.transaction(|conn| {
+          for chunk in logs.chunks(SQLITE_MAX_ROWS) {
+            diesel::RunQueryDsl::execute(
+              diesel::insert_into(sqlite_logs::table).values(chunk),
+              conn,
+            )?;
+          }
+          Ok::<_, DieselError>(())
+        })
+      })
+      .await?;
+    Ok(())
+  }
+
+  async fn count(&self, filter: &LogFilter) -> anyhow::Result<i64> {
+    let mut conn = self.pool.get().await?;
+    let count = filter_logs!(sqlite_logs::table.into_boxed(), sqlite_logs, filter)
+      .count()
+      .get_result(&mut conn)
+      .await?;
+    Ok(count)
+  }
+
+  async fn list(
+    &self,
+    filter: &LogFilter,
+    after: Option<&LogKey>,
+    offset: i64,
+    limit: i64,
+  ) -> anyhow::Result<Vec<Log>> {
+    let mut conn = self.pool.get().await?;
+    let query = filter_logs!(sqlite_logs::table.into_boxed(), sqlite_logs, filter);
+    let logs: Vec<SqliteLog> = after_key!(query, after, TimestamptzSqlite)
+      .limit(limit)
+      .offset(offset)
+      .order_by((sqlite_logs::time, sqlite_logs::id))
+      .get_results(&mut conn)
+      .await?;
+    logs
+      .into_iter()
+      .map(Log::try_from)
+      .collect::<Result<_, _>>()
+      .context("Malformed raw_json")
+  }
+
+  async fn saved_at(&self, room_id: i64, times: &[DateTime<Utc>]) -> anyhow::Result<Vec<Log>> {
+    let mut conn = self.pool.get().await?;
+    let logs: Vec<SqliteLog> = sqlite_logs::table
+      .filter(sqlite_logs::room_id.eq(room_id))
+      .filter(sqlite_logs::time.eq_any(times))
+      .get_results(&mut conn)
+      .await?;
+    logs
+      .into_iter()
+      .map(Log::try_from)
+      .collect::<Result<_, _>>()
+      .context("Malformed raw_json")
+  }
+
+  async fn stats(&self, filter: &LogFilter) -> anyhow::Result<Vec<CommandCount>> {
+    let mut conn = self.pool.get().await?;
+    let rows: Vec<(String, i64)> = filter_logs!(
+      sqlite_logs::table
+        .group_by(sqlite_logs::command)
+        .select((sqlite_logs::command, count_star()))
+        .into_boxed(),
+      sqlite_logs,
+      filter
+    )
+    .load(&mut conn)
+    .await?;
+    let mut counts: Vec<CommandCount> = rows
+      .into_iter()
+      .map(|(command, count)| CommandCount { command, count })
+      .collect();
+    CommandCount::sort(&mut counts);
+    Ok(counts)
+  }
+
+  async fn prune(
+    &self,
+    scope: PruneScope<'_>,
+    before: DateTime<Utc>,
+    limit: i64,
+  ) -> anyhow::Result<usize> {
+    let mut conn = self.pool.get().await?;
+    let ids = prune_ids!(sqlite_logs, scope, before, limit);
+    let deleted = diesel::delete(sqlite_logs::table.filter(sqlite_logs::id.eq_any(ids)))
+      .execute(&mut conn)
+      .await?;
+    Ok(deleted)
+  }
+}
+
+/// Keeps logs in memory, for tests and trying out without a database
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+  logs: RwLock<Vec<Log>>,
+  /// Ids are never reused after pruning, as pagination relies on them
+  last_id: AtomicI64,
+}
+
+#[async_trait]
+impl LogStore for MemoryStore {
+  async fn insert(&self, logs: Vec<(&NewLog, Option<&TypedRow>)>) -> Result<(), StoreError> {
+    let mut saved = self.logs.write().unwrap();
+    for (log, _) in logs {
+      let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+      saved.push(Log {
+        id,
+        room_id: log.room_id,
+        command: log.command.clone(),
+        raw_json: log.raw_json.clone(),
+        time: log.time,
+        related_uid: log.related_uid,
+      });
+    }
+    Ok(())
+  }
+
+  async fn count(&self, filter: &LogFilter) -> anyhow::Result<i64> {
+    let logs = self.logs.read().unwrap();
+    Ok(logs.iter().filter(|log| filter.matches(log)).count() as i64)
+  }
+
+  async fn list(
+    &self,
+    filter: &LogFilter,
+    after: Option<&LogKey>,
+    offset: i64,
+    limit: i64,
+  ) -> anyhow::Result<Vec<Log>> {
+    let logs = self.logs.read().unwrap();
+    let mut logs: Vec<Log> = logs
+      .iter()
+      .filter(|log| filter.matches(log))
+      .filter(|log| after.is_none_or(|after| (log.time, log.id) > (after.time, after.id)))
+      .cloned()
+      .collect();
+    logs.sort_by_key(|log| (log.time, log.id));
+    Ok(
+      logs
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect(),
+    )
+  }
+
+  async fn stats(&self, filter: &LogFilter) -> anyhow::Result<Vec<CommandCount>> {
+    let logs = self.logs.read().unwrap();
+    let mut counts: AHashMap<&str, i64> = AHashMap::new();
+    for log in logs.iter().filter(|log| filter.matches(log)) {
+      *counts.entry(&log.command).or_default() += 1;
+    }
+    let mut counts: Vec<CommandCount> = counts
+      .into_iter()
+      .map(|(command, count)| CommandCount {
+        command: command.to_string(),
+        count,
+      })
+      .collect();
+    CommandCount::sort(&mut counts);
+    Ok(counts)
+  }
+
+  async fn saved_at(&self, room_id: i64, times: &[DateTime<Utc>]) -> anyhow::Result<Vec<Log>> {
+    let logs = self.logs.read().unwrap();
+    Ok(
+      logs
+        .iter()
+        .filter(|log| log.room_id == room_id && times.contains(&log.time))
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn prune(
+    &self,
+    scope: PruneScope<'_>,
+    before: DateTime<Utc>,
+    limit: i64,
+  ) -> anyhow::Result<usize> {
+    let mut logs = self.logs.write().unwrap();
+    let mut deleted = 0;
+    logs.retain(|log| {
+      let prune = deleted < limit && log.time < before && scope.contains(&log.command);
+      deleted += prune as i64;
+      !prune
+    });
+    Ok(deleted as usize)
+  }
+}
+
+fn run_migrations<DB: diesel::backend::Backend>(
+  conn: &mut impl MigrationHarness<DB>,
+  migrations: EmbeddedMigrations,
+) -> anyhow::Result<()> {
+  log::info!("Running database migrations...");
+  let versions: Vec<MigrationVersion> = conn
+    .run_pending_migrations(migrations)
+    .map_err(|err| anyhow!(err))
+    .context("Failed to run migrations")?;
+  if let Some(last) = versions.last() {
+    log::info!("Current migration version: {last}");
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn log_key_round_trip() {
+    let key = LogKey {
+      time: DateTime::from_timestamp(1704164645, 678_901_234).unwrap(),
+      id: 42,
+    };
+    assert_eq!(key.encode(), "1704164645_678901234_42");
+    assert_eq!(LogKey::decode(&key.encode()), Some(key));
+    assert_eq!(LogKey::decode("1704164645_678901234"), None);
+    assert_eq!(LogKey::decode("1704164645_x_42"), None);
+  }
+
+  #[tokio::test]
+  async fn memory_ids_after_prune() {
+    let store = MemoryStore::default();
+    let log = |command: &str, secs: i64| NewLog {
+      room_id: 1,
+      command: command.to_string(),
+      raw_json: serde_json::json!({ "cmd": command }),
+      time: DateTime::from_timestamp(1700000000 + secs, 0).unwrap(),
+      related_uid: None,
+    };
+    let (old, kept, new) = (log("LIVE", 0), log("DANMU_MSG", 1), log("DANMU_MSG", 2));
+    assert!(store
+      .insert(vec![(&old, None), (&kept, None)])
+      .await
+      .is_ok());
+    let before = DateTime::from_timestamp(1700000001, 0).unwrap();
+    let pruned = store.prune(PruneScope::Command("LIVE"), before, 10).await;
+    assert_eq!(pruned.unwrap(), 1);
+    assert!(store.insert(vec![(&new, None)]).await.is_ok());
+
+    let filter = LogFilter {
+      room_id: 1,
+      ..Default::default()
+    };
+    let logs = store.list(&filter, None, 0, 10).await.unwrap();
+    let ids: Vec<_> = logs.iter().map(|log| log.id).collect();
+    assert_eq!(ids, [2, 3]);
+  }
+}
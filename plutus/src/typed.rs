@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use diesel::{result::QueryResult, ExpressionMethods, QueryDsl};
+use diesel_async::{
+  scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use plutus_core::data::live::cmds::{CoinType, Command};
+
+use crate::{
+  models::{NewDanmaku, NewGift, NewGuardBuy, NewSuperChat},
+  schema::{danmaku, gifts, guard_buys, super_chats},
+  store::{self, PruneScope},
+};
+
+/// Commands saved in typed tables as well, only with PostgreSQL
+pub const TYPED_COMMANDS: &[&str] = &["DANMU_MSG", "SUPER_CHAT_MESSAGE", "GUARD_BUY", "SEND_GIFT"];
+
+/// tokio-postgres sends at most 32767 bind parameters in a statement, `danmaku` and `gifts` have 12 columns.
+const MAX_ROWS: usize = 32767 / 12;
+
+/// Row of a typed table parsed from the command of a log
+#[derive(Debug, Clone)]
+pub enum TypedRow {
+  Danmaku(NewDanmaku),
+  SuperChat(NewSuperChat),
+  GuardBuy(NewGuardBuy),
+  Gift(NewGift),
+}
+
+impl TypedRow {
+  pub fn from_command(cmd: &Command) -> Option<TypedRow> {
+    let row = match cmd {
+      Command::Danmaku { data } => {
+        let data = data.data().ok()?;
+        let medal = data.medal.as_ref();
+        TypedRow::Danmaku(NewDanmaku {
+          uid: data.user.uid as i64,
+          username: data.user.username.clone(),
+          content: data.content.clone(),
+          mode: data.metadata.mode.clone() as i16,
+          is_emoticon: data.metadata.is_emoticon,
+          medal_name: medal.map(|medal| medal.name.clone()),
+          medal_level: medal.map(|medal| medal.level as i32),
+          medal_liver_uid: medal.map(|medal| medal.liver_uid as i64),
+          medal_guard_level: medal.map(|medal| medal.guard_level as i16),
+        })
+      },
+      Command::SuperChatMessage { data } => TypedRow::SuperChat(NewSuperChat {
+        id: data.id as i64,
+        uid: data.uid as i64,
+        username: data.user.username.clone(),
+        price: data.price as i32,
+        message: data.message.clone(),
+        translation: data.translate.clone(),
+        duration_secs: data.time.duration.as_secs() as i32,
+      }),
+      Command::GuardBuy { data } => TypedRow::GuardBuy(NewGuardBuy {
+        uid: data.uid as i64,
+        username: data.username.clone(),
+        guard_level: data.guard_level as i16,
+        price: data.price as i64,
+        num: data.num as i32,
+        gift_name: data.gift_name.clone(),
+      }),
+      Command::SendGift { data } => TypedRow::Gift(NewGift {
+        uid: data.uid as i64,
+        username: data.username.clone(),
+        gift_id: data.gift_id as i64,
+        gift_name: data.gift_name.clone(),
+        action: data.action.clone(),
+        num: data.num as i32,
+        coin_type: match data.coin_type {
+          CoinType::Gold => "gold",
+          CoinType::Silver => "silver",
+          CoinType::Unknown => "unknown",
+        }
+        .to_string(),
+        price: data.price as i64,
+        total_coin: data.total_coin as i64,
+      }),
+      _ => return None,
+    };
+    Some(row)
+  }
+}
+
+/// Inserts typed rows of saved logs as `(log_id, room_id, time, row)`,
+/// rows of existing `log_id` are skipped. Each table is inserted in chunks of [`MAX_ROWS`].
+pub async fn insert<'a>(
+  conn: &mut AsyncPgConnection,
+  rows: impl IntoIterator<Item = (i64, i64, DateTime<Utc>, &'a TypedRow)>,
+) -> QueryResult<()> {
+  let mut danmaku_rows = Vec::new();
+  let mut super_chat_rows = Vec::new();
+  let mut guard_buy_rows = Vec::new();
+  let mut gift_rows = Vec::new();
+  for (log_id, room_id, time, row) in rows {
+    match row {
+      TypedRow::Danmaku(row) => danmaku_rows.push((
+        danmaku::log_id.eq(log_id),
+        danmaku::room_id.eq(room_id),
+        danmaku::time.eq(time),
+        row,
+      )),
+      TypedRow::SuperChat(row) => super_chat_rows.push((
+        super_chats::log_id.eq(log_id),
+        super_chats::room_id.eq(room_id),
+        super_chats::time.eq(time),
+        row,
+      )),
+      TypedRow::GuardBuy(row) => guard_buy_rows.push((
+        guard_buys::log_id.eq(log_id),
+        guard_buys::room_id.eq(room_id),
+        guard_buys::time.eq(time),
+        row,
+      )),
+      TypedRow::Gift(row) => gift_rows.push((
+        gifts::log_id.eq(log_id),
+        gifts::room_id.eq(room_id),
+        gifts::time.eq(time),
+        row,
+      )),
+    }
+  }
+  macro_rules! insert_rows {
+    ($table:ident, $rows:expr) => {
+      for chunk in $rows.chunks(MAX_ROWS) {
+        diesel::insert_into($table::table)
+          .values(chunk.to_vec())
+          .on_conflict_do_nothing()
+          .execute(conn)
+          .await?;
+      }
+    };
+  }
+  insert_rows!(danmaku, danmaku_rows);
+  insert_rows!(super_chats, super_chat_rows);
+  insert_rows!(guard_buys, guard_buy_rows);
+  insert_rows!(gifts, gift_rows);
+  Ok(())
+}
+
+/// Inserts typed rows in a savepoint, so rejected ones don't roll back their raw logs in the same transaction.
+/// If the rows are rejected, they are retried one by one, and the rejected ones are logged and skipped.
+pub async fn insert_or_skip(
+  conn: &mut AsyncPgConnection,
+  rows: &[(i64, i64, DateTime<Utc>, &TypedRow)],
+) -> QueryResult<()> {
+  if rows.is_empty() {
+    return Ok(());
+  }
+  let result = conn
+    .transaction(|conn| insert(conn, rows.iter().copied()).scope_boxed())
+    .await;
+  match result {
+    Ok(()) => return Ok(()),
+    Err(err) if store::is_unavailable(&err) => return Err(err),
+    Err(err) => log::warn!("Typed rows are rejected, {err}, inserting one by one"),
+  }
+  for row in rows {
+    let result = conn
+      .transaction(|conn| insert(conn, [*row]).scope_boxed())
+      .await;
+    match result {
+      Err(err) if store::is_unavailable(&err) => return Err(err),
+      Err(err) => log::error!("Skipped typed row of log {}, {err}: {:?}", row.0, row.3),
+      Ok(()) => {},
+    }
+  }
+  Ok(())
+}
+
+/// Deletes typed rows of the scope before `before`, at most `limit` rows per table.
+/// Returns the most rows deleted from a table.
+pub async fn prune(
+  conn: &mut AsyncPgConnection,
+  scope: PruneScope<'_>,
+  before: DateTime<Utc>,
+  limit: i64,
+) -> QueryResult<usize> {
+  let mut deleted = 0;
+  macro_rules! prune_table {
+    ($command:literal, $table:ident) => {
+      if scope.contains($command) {
+        let ids = $table::table
+          .filter($table::time.lt(before))
+          .select($table::log_id)
+          .limit(limit)
+          .into_boxed();
+        let rows = diesel::delete($table::table.filter($table::log_id.eq_any(ids)))
+          .execute(conn)
+          .await?;
+        deleted = deleted.max(rows);
+      }
+    };
+  }
+  prune_table!("DANMU_MSG", danmaku);
+  prune_table!("SUPER_CHAT_MESSAGE", super_chats);
+  prune_table!("GUARD_BUY", guard_buys);
+  prune_table!("SEND_GIFT", gifts);
+  Ok(deleted)
+}
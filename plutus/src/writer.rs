@@ -0,0 +1,357 @@
+use std::{slice, time::Duration};
+
+use anyhow::{bail, Context};
+use plutus_core::data::live::cmds::{Command, MaybeCommand};
+use tokio::{
+  sync::mpsc::{self, error::SendTimeoutError},
+  task::{block_in_place, JoinHandle},
+  time::Instant,
+};
+
+use crate::{
+  config::WriterConfig,
+  error::AnyhowExt,
+  global_state, metrics,
+  models::NewLog,
+  sessions,
+  spool::{Spool, Spooled},
+  store::StoreError,
+  typed::{TypedRow, TYPED_COMMANDS},
+};
+
+/// tokio-postgres sends at most 32767 bind parameters in a statement, as their count is an Int16,
+/// and `logs` has 5 columns. Typed rows have more columns, they are inserted in smaller chunks
+/// by [`crate::typed::insert`].
+const MAX_BATCH_SIZE: usize = 32767 / 5;
+
+/// How often the spool is retried while no log is received
+const REPLAY_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Pending {
+  log: NewLog,
+  /// Only `LIVE` and `PREPARING`, applied after the log is saved
+  session_cmd: Option<Command>,
+  typed: Option<TypedRow>,
+  imported: bool,
+}
+
+impl Pending {
+  fn new(log: NewLog, cmd: Option<Command>, imported: bool) -> Pending {
+    let typed = cmd.as_ref().and_then(TypedRow::from_command);
+    // Archived commands would close or reopen the current session, see `sessions::rebuild`
+    let session_cmd = cmd.filter(|cmd| !imported && sessions::is_session_command(cmd));
+    Pending {
+      log,
+      session_cmd,
+      typed,
+      imported,
+    }
+  }
+
+  /// Restores a spooled log, session and typed commands are parsed again from the raw json.
+  fn from_spool(Spooled { log, imported }: Spooled) -> Pending {
+    let cmd = match log.command.as_str() {
+      "LIVE" | "PREPARING" => serde_json::from_value::<MaybeCommand>(log.raw_json.clone()).ok(),
+      command if TYPED_COMMANDS.contains(&command) => {
+        serde_json::from_value::<MaybeCommand>(log.raw_json.clone()).ok()
+      },
+      _ => None,
+    };
+    let cmd = match cmd {
+      Some(MaybeCommand::Command(cmd)) => Some(cmd),
+      _ => None,
+    };
+    Pending::new(log, cmd, imported)
+  }
+
+  fn spooled(&self) -> Spooled<&NewLog> {
+    Spooled {
+      log: &self.log,
+      imported: self.imported,
+    }
+  }
+
+  fn record(&self) -> (&NewLog, Option<&TypedRow>) {
+    (&self.log, self.typed.as_ref())
+  }
+}
+
+/// Queues collected logs and inserts them in batches by a single task,
+/// so bursts don't hold one pooled connection per message.
+/// Logs are spooled to disk while the database is unavailable.
+pub struct LogWriter {
+  tx: mpsc::Sender<Pending>,
+  enqueue_timeout: Duration,
+  task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl LogWriter {
+  /// Spawns the writer task
+  pub fn start(config: &WriterConfig) -> anyhow::Result<LogWriter> {
+    let spool = Spool::open(&config.spool_dir)?;
+    Ok(LogWriter::spawn(config, Some(spool)))
+  }
+
+  /// Spawns the writer task without a spool, which stops once the database is unavailable,
+  /// see [`LogWriter::close`]. The spool is left to the server, which replays and consumes it.
+  pub fn start_without_spool(config: &WriterConfig) -> LogWriter {
+    LogWriter::spawn(config, None)
+  }
+
+  fn spawn(config: &WriterConfig, spool: Option<Spool>) -> LogWriter {
+    let (tx, rx) = mpsc::channel(config.queue_size.max(1));
+    let batch_size = config.batch_size.clamp(1, MAX_BATCH_SIZE);
+    let flush_interval = Duration::from_millis(config.flush_interval_ms);
+    let task = tokio::spawn(run(rx, spool, batch_size, flush_interval));
+    LogWriter {
+      tx,
+      enqueue_timeout: Duration::from_millis(config.enqueue_timeout_ms),
+      task,
+    }
+  }
+
+  /// Queues an archived log, waiting as long as the queue is full so nothing is dropped.
+  /// Imported logs don't open or close sessions.
+  pub async fn write_imported(&self, log: NewLog, cmd: Option<Command>) -> anyhow::Result<()> {
+    self
+      .tx
+      .send(Pending::new(log, cmd, true))
+      .await
+      .map_err(|_| anyhow::anyhow!("Writer is stopped"))
+  }
+
+  /// Stops accepting logs and waits until queued logs are saved or spooled.
+  /// Without a spool, returns why the writer stopped early if the database became unavailable.
+  pub async fn close(self) -> anyhow::Result<()> {
+    drop(self.tx);
+    self.task.await.context("Writer task panicked")?
+  }
+
+  /// Waits while the queue is full, the log is dropped if it is still full after the timeout.
+  pub async fn write(&self, log: NewLog, cmd: Option<Command>) {
+    let room_id = log.room_id;
+    let pending = Pending::new(log, cmd, false);
+    if let Err(err) = self.tx.send_timeout(pending, self.enqueue_timeout).await {
+      metrics()
+        .dropped_rows
+        .with_label_values(&[&room_id.to_string()])
+        .inc();
+      match err {
+        SendTimeoutError::Timeout(pending) => {
+          log::warn!("Writer queue is full, dropped {:?}", pending.log)
+        },
+        SendTimeoutError::Closed(pending) => {
+          log::error!("Writer is stopped, dropped {:?}", pending.log)
+        },
+      }
+    }
+  }
+}
+
+async fn run(
+  mut rx: mpsc::Receiver<Pending>,
+  mut spool: Option<Spool>,
+  batch_size: usize,
+  flush_interval: Duration,
+) -> anyhow::Result<()> {
+  let mut batch: Vec<Pending> = Vec::with_capacity(batch_size);
+  let mut replay_at = Instant::now();
+  loop {
+    let received = match spool {
+      Some(ref mut spool) if spool.is_pending() => {
+        match tokio::time::timeout(REPLAY_INTERVAL, rx.recv_many(&mut batch, batch_size)).await {
+          Ok(received) => received,
+          Err(_elapsed) => {
+            try_replay(spool, batch_size, &mut replay_at).await.log();
+            continue;
+          },
+        }
+      },
+      _ => rx.recv_many(&mut batch, batch_size).await,
+    };
+    if received == 0 {
+      log::info!("Writer queue is closed");
+      return Ok(());
+    }
+    let deadline = Instant::now() + flush_interval;
+    while batch.len() < batch_size {
+      let limit = batch_size - batch.len();
+      match tokio::time::timeout_at(deadline, rx.recv_many(&mut batch, limit)).await {
+        Ok(0) | Err(_) => break,
+        Ok(_) => {},
+      }
+    }
+    metrics().writer_queue.set(rx.len() as i64);
+    match spool {
+      Some(ref mut spool) => flush(&batch, spool, batch_size, &mut replay_at).await,
+      None => {
+        if let Err((_saved, err)) = save(&batch).await {
+          return Err(err).context("Database is unavailable");
+        }
+      },
+    }
+    batch.clear();
+  }
+}
+
+async fn flush(batch: &[Pending], spool: &mut Spool, batch_size: usize, replay_at: &mut Instant) {
+  // Spooled logs go first to keep the order, new logs are spooled after them otherwise
+  #[allow(clippy::collapsible_if)]
+  if spool.is_pending() {
+    if let Err(err) = try_replay(spool, batch_size, replay_at).await {
+      log::warn!(
+        "Database is still unavailable, spooling {} logs: {err:?}",
+        batch.len()
+      );
+      append_spool(spool, batch);
+      return;
+    }
+  }
+  if let Err((saved, err)) = save(batch).await {
+    log::warn!(
+      "Database is unavailable, spooling {} logs: {err:?}",
+      batch.len() - saved
+    );
+    append_spool(spool, &batch[saved..]);
+    *replay_at = Instant::now() + REPLAY_INTERVAL;
+  }
+}
+
+/// Replays the spool unless the database was found unavailable within [`REPLAY_INTERVAL`],
+/// so an outage doesn't read the spool on every flush.
+async fn try_replay(
+  spool: &mut Spool,
+  batch_size: usize,
+  replay_at: &mut Instant,
+) -> anyhow::Result<()> {
+  if Instant::now() < *replay_at {
+    bail!("Database was unavailable less than {REPLAY_INTERVAL:?} ago");
+  }
+  let result = replay(spool, batch_size).await;
+  if result.is_err() {
+    *replay_at = Instant::now() + REPLAY_INTERVAL;
+  }
+  result
+}
+
+/// Replays spooled logs in order, by chunks of `batch_size`.
+/// Replayed logs are dropped from the spool once the database is unavailable again, or the file is done.
+async fn replay(spool: &mut Spool, batch_size: usize) -> anyhow::Result<()> {
+  for (room_id, path) in block_in_place(|| spool.files())? {
+    let mut offset = 0;
+    let mut replayed = 0;
+    loop {
+      let chunk = block_in_place(|| spool.read(&path, offset, batch_size))?;
+      if chunk.end == offset {
+        break;
+      }
+      let pendings: Vec<Pending> = chunk.logs.into_iter().map(Pending::from_spool).collect();
+      if let Err((saved, err)) = save(&pendings).await {
+        if saved > 0 {
+          offset = chunk.ends[saved - 1];
+        }
+        if offset > 0 {
+          block_in_place(|| spool.consume(&path, offset))?;
+        }
+        return Err(err).with_context(|| format!("Failed to replay spool of room {room_id}"));
+      }
+      replayed += pendings.len();
+      offset = chunk.end;
+    }
+    block_in_place(|| spool.consume(&path, offset))?;
+    log::info!("Replayed {replayed} spooled logs of room {room_id}");
+  }
+  Ok(())
+}
+
+fn append_spool(spool: &mut Spool, batch: &[Pending]) {
+  let result = block_in_place(|| spool.append(batch.iter().map(Pending::spooled)));
+  match result {
+    Ok(()) => {
+      for pending in batch {
+        metrics()
+          .spooled_rows
+          .with_label_values(&[&pending.log.room_id.to_string()])
+          .inc();
+      }
+    },
+    Err(err) => {
+      for pending in batch {
+        metrics()
+          .db_insert_failures
+          .with_label_values(&[&pending.log.room_id.to_string()])
+          .inc();
+      }
+      log::error!(
+        "Failed to spool {} logs, they are lost: {err:?}",
+        batch.len()
+      );
+    },
+  }
+}
+
+/// Inserts the batch in one statement, and one by one if it is rejected,
+/// so a single bad log doesn't fail the others.
+/// Returns the number of logs handled before the database became unavailable on error.
+async fn save(batch: &[Pending]) -> Result<(), (usize, anyhow::Error)> {
+  match insert(batch.iter().map(Pending::record).collect()).await {
+    Ok(()) => {
+      track_sessions(batch).await;
+      return Ok(());
+    },
+    Err(StoreError::Unavailable(err)) => return Err((0, err)),
+    Err(StoreError::Rejected(err)) if batch.len() > 1 => {
+      log::warn!("{err:?}, inserting one by one");
+    },
+    Err(StoreError::Rejected(_)) => {},
+  }
+  for (idx, pending) in batch.iter().enumerate() {
+    match insert(vec![pending.record()]).await {
+      Ok(()) => track_sessions(slice::from_ref(pending)).await,
+      Err(StoreError::Unavailable(err)) => return Err((idx, err)),
+      Err(StoreError::Rejected(err)) => {
+        metrics()
+          .db_insert_failures
+          .with_label_values(&[&pending.log.room_id.to_string()])
+          .inc();
+        log::error!("Failed to insert, {:?}, err: {err:?}", pending.log);
+      },
+    }
+  }
+  Ok(())
+}
+
+async fn insert(logs: Vec<(&NewLog, Option<&TypedRow>)>) -> Result<(), StoreError> {
+  let len = logs.len();
+  let timer = metrics().db_insert_seconds.start_timer();
+  let result = global_state().store().insert(logs).await;
+  timer.observe_duration();
+  result.map_err(|err| match err {
+    StoreError::Rejected(err) => {
+      StoreError::Rejected(err.context(format!("Failed to insert {len} logs")))
+    },
+    err => err,
+  })
+}
+
+async fn track_sessions(batch: &[Pending]) {
+  if !global_state().is_postgres() || batch.iter().all(|pending| pending.session_cmd.is_none()) {
+    return;
+  }
+  let mut conn = match global_state().db_con().await {
+    Ok(conn) => conn,
+    Err(err) => {
+      log::error!("Failed to track sessions: {err:?}");
+      return;
+    },
+  };
+  for pending in batch {
+    if let Some(ref cmd) = pending.session_cmd {
+      let room_id = pending.log.room_id as u64;
+      sessions::on_command(&mut conn, room_id, cmd, pending.log.time)
+        .await
+        .with_context(|| format!("Failed to track session of room {room_id}"))
+        .log();
+    }
+  }
+}